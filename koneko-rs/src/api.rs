@@ -0,0 +1,477 @@
+use std::cell::RefCell;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::auth::{self, Tokens};
+use crate::config::{self, Credentials};
+
+const BASE_URL: &str = "https://app-api.pixiv.net";
+const USER_AGENT: &str = "PixivIOSApp/7.6.2 (iOS 12.2; iPhone9,1)";
+
+pub enum SearchTarget {
+    PartialTags,
+    ExactTags,
+    TitleAndCaption,
+}
+
+impl SearchTarget {
+    pub fn from_key(key: &str) -> SearchTarget {
+        match key {
+            "e" => SearchTarget::ExactTags,
+            "t" => SearchTarget::TitleAndCaption,
+            _ => SearchTarget::PartialTags,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            SearchTarget::PartialTags => "partial_match_for_tags",
+            SearchTarget::ExactTags => "exact_match_for_tags",
+            SearchTarget::TitleAndCaption => "title_and_caption",
+        }
+    }
+}
+
+pub enum SearchSort {
+    DateDesc,
+    DateAsc,
+}
+
+impl SearchSort {
+    pub fn from_key(key: &str) -> SearchSort {
+        match key {
+            "o" => SearchSort::DateAsc,
+            _ => SearchSort::DateDesc,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            SearchSort::DateDesc => "date_desc",
+            SearchSort::DateAsc => "date_asc",
+        }
+    }
+}
+
+pub enum RankingMode {
+    Daily,
+    Weekly,
+    Monthly,
+    Rookie,
+    Original,
+}
+
+impl RankingMode {
+    pub fn from_key(key: &str) -> RankingMode {
+        match key {
+            "w" => RankingMode::Weekly,
+            "m" => RankingMode::Monthly,
+            "r" => RankingMode::Rookie,
+            "o" => RankingMode::Original,
+            _ => RankingMode::Daily,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            RankingMode::Daily => "day",
+            RankingMode::Weekly => "week",
+            RankingMode::Monthly => "month",
+            RankingMode::Rookie => "week_rookie",
+            RankingMode::Original => "week_original",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Restrict {
+    Public,
+    Private,
+}
+
+impl Restrict {
+    pub fn from_key(key: &str) -> Restrict {
+        match key {
+            "2" => Restrict::Private,
+            _ => Restrict::Public,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Restrict::Public => "public",
+            Restrict::Private => "private",
+        }
+    }
+
+    pub fn toggle(&self) -> Restrict {
+        match self {
+            Restrict::Public => Restrict::Private,
+            Restrict::Private => Restrict::Public,
+        }
+    }
+}
+
+type SaveToken = Box<dyn Fn(&str)>;
+
+pub struct Api {
+    pub base_url: String,
+    /// Replaced when it expires, see `call`
+    access_token: RefCell<String>,
+    /// pixiv may give a new one with every refresh, see `refresh`
+    refresh_token: RefCell<String>,
+    /// Keeps a new refresh token for the next start
+    save_refresh_token: Option<SaveToken>,
+    auth_url: String,
+    /// The logged in user's ID, as given by the token response
+    pub user_id: String,
+    agent: ureq::Agent,
+}
+
+impl Api {
+    pub fn new(base_url: &str, access_token: &str) -> Api {
+        Api {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            access_token: RefCell::new(access_token.to_string()),
+            refresh_token: RefCell::new(String::new()),
+            save_refresh_token: None,
+            auth_url: auth::AUTH_URL.to_string(),
+            user_id: String::new(),
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(30))
+                .build(),
+        }
+    }
+
+    /// Lets the access token be refreshed with `refresh_token` at `auth_url`
+    pub fn with_refresh_token(mut self, auth_url: &str, refresh_token: &str) -> Api {
+        self.auth_url = auth_url.to_string();
+        self.refresh_token = RefCell::new(refresh_token.to_string());
+        self
+    }
+
+    /// Calls `save` with the refresh token whenever pixiv replaces it
+    pub fn on_new_refresh_token(mut self, save: impl Fn(&str) + 'static) -> Api {
+        self.save_refresh_token = Some(Box::new(save));
+        self
+    }
+
+    fn from_tokens(tokens: Tokens) -> Api {
+        let mut api = Api::new(BASE_URL, &tokens.access_token)
+            .with_refresh_token(auth::AUTH_URL, &tokens.refresh_token);
+        api.user_id = tokens.user_id;
+        api
+    }
+
+    /// Gets a new access token with the stored refresh token, and saves
+    /// the refresh token where it was if pixiv replaced it
+    pub fn login(creds: &Credentials) -> Option<Api> {
+        let passphrase = creds.passphrase.clone();
+        let save = move |refresh_token: &str| {
+            if config::save_refresh_token(refresh_token, passphrase.as_deref()).is_none() {
+                println!("Failed to save the new login!");
+            }
+        };
+        let api = Api::from_tokens(auth::refresh(auth::AUTH_URL, &creds.refresh_token)?);
+        if *api.refresh_token.borrow() != creds.refresh_token {
+            save(&api.refresh_token.borrow());
+        }
+        Some(api.on_new_refresh_token(save))
+    }
+
+    fn refresh(&self) -> Option<()> {
+        if self.refresh_token.borrow().is_empty() {
+            return None;
+        }
+        let tokens = auth::refresh(&self.auth_url, &self.refresh_token.borrow())?;
+        *self.access_token.borrow_mut() = tokens.access_token;
+        if *self.refresh_token.borrow() != tokens.refresh_token {
+            if let Some(save) = &self.save_refresh_token {
+                save(&tokens.refresh_token);
+            }
+            *self.refresh_token.borrow_mut() = tokens.refresh_token;
+        }
+        Some(())
+    }
+
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        self.agent
+            .request(method, url)
+            .set("User-Agent", USER_AGENT)
+            .set("App-OS", "ios")
+            .set(
+                "Authorization",
+                &format!("Bearer {}", self.access_token.borrow()),
+            )
+    }
+
+    /// Sends a request, and if the access token has expired, refreshes
+    /// it and sends the request again
+    fn call(&self, method: &str, url: &str, params: &[(&str, &str)]) -> Option<Value> {
+        let response = match self.send(method, url, params) {
+            Err(ureq::Error::Status(code, response)) => {
+                let body = response.into_string().unwrap_or_default();
+                if !token_expired(code, &body) {
+                    return None;
+                }
+                self.refresh()?;
+                self.send(method, url, params).ok()?
+            }
+            response => response.ok()?,
+        };
+        serde_json::from_str(&response.into_string().ok()?).ok()
+    }
+
+    /// `params` are sent as the query string for GET, and as a form for POST
+    #[allow(clippy::result_large_err)]
+    fn send(
+        &self,
+        method: &str,
+        url: &str,
+        params: &[(&str, &str)],
+    ) -> Result<ureq::Response, ureq::Error> {
+        let request = self.request(method, url);
+        if method == "POST" {
+            request.send_form(params)
+        } else {
+            params
+                .iter()
+                .fold(request, |request, (key, value)| request.query(key, value))
+                .call()
+        }
+    }
+
+    fn get(&self, url: &str, params: &[(&str, &str)]) -> Option<Value> {
+        self.call("GET", url, params)
+    }
+
+    fn post(&self, url: &str, form: &[(&str, &str)]) -> Option<Value> {
+        self.call("POST", url, form)
+    }
+
+    /// Requests the `next_url` given by a previous response, pointed at our base url
+    pub fn next_page(&self, next_url: &str) -> Option<Value> {
+        self.get(&next_url.replacen(BASE_URL, &self.base_url, 1), &[])
+    }
+
+    pub fn search_illust(
+        &self,
+        word: &str,
+        search_target: &SearchTarget,
+        sort: &SearchSort,
+    ) -> Option<Value> {
+        self.get(
+            &format!("{}/v1/search/illust", self.base_url),
+            &[
+                ("word", word),
+                ("search_target", search_target.as_str()),
+                ("sort", sort.as_str()),
+                ("filter", "for_ios"),
+            ],
+        )
+    }
+
+    /// `date` is in YYYY-MM-DD; without it, the latest ranking is returned
+    pub fn illust_ranking(&self, mode: &RankingMode, date: Option<&str>) -> Option<Value> {
+        let mut params = vec![("mode", mode.as_str()), ("filter", "for_ios")];
+        if let Some(date) = date {
+            params.push(("date", date));
+        }
+        self.get(&format!("{}/v1/illust/ranking", self.base_url), &params)
+    }
+
+    pub fn user_bookmarks_illust(
+        &self,
+        user_id: &str,
+        restrict: Restrict,
+        tag: Option<&str>,
+    ) -> Option<Value> {
+        let mut params = vec![
+            ("user_id", user_id),
+            ("restrict", restrict.as_str()),
+            ("filter", "for_ios"),
+        ];
+        if let Some(tag) = tag {
+            params.push(("tag", tag));
+        }
+        self.get(&format!("{}/v1/user/bookmarks/illust", self.base_url), &params)
+    }
+
+    pub fn illust_bookmark_add(
+        &self,
+        illust_id: &str,
+        restrict: Restrict,
+        tags: &[String],
+    ) -> Option<Value> {
+        let mut form = vec![("illust_id", illust_id), ("restrict", restrict.as_str())];
+        for tag in tags {
+            form.push(("tags[]", tag));
+        }
+        self.post(&format!("{}/v2/illust/bookmark/add", self.base_url), &form)
+    }
+
+    pub fn illust_bookmark_delete(&self, illust_id: &str) -> Option<Value> {
+        self.post(
+            &format!("{}/v1/illust/bookmark/delete", self.base_url),
+            &[("illust_id", illust_id)],
+        )
+    }
+
+    pub fn user_illusts(&self, user_id: &str) -> Option<Value> {
+        self.get(
+            &format!("{}/v1/user/illusts", self.base_url),
+            &[("user_id", user_id), ("type", "illust"), ("filter", "for_ios")],
+        )
+    }
+
+    pub fn user_following(&self, user_id: &str, restrict: Restrict) -> Option<Value> {
+        self.get(
+            &format!("{}/v1/user/following", self.base_url),
+            &[("user_id", user_id), ("restrict", restrict.as_str())],
+        )
+    }
+
+    pub fn user_follow_add(&self, user_id: &str, restrict: Restrict) -> Option<Value> {
+        self.post(
+            &format!("{}/v1/user/follow/add", self.base_url),
+            &[("user_id", user_id), ("restrict", restrict.as_str())],
+        )
+    }
+
+    pub fn user_follow_delete(&self, user_id: &str) -> Option<Value> {
+        self.post(
+            &format!("{}/v1/user/follow/delete", self.base_url),
+            &[("user_id", user_id)],
+        )
+    }
+
+    pub fn illust_related(&self, illust_id: &str) -> Option<Value> {
+        self.get(
+            &format!("{}/v2/illust/related", self.base_url),
+            &[("illust_id", illust_id), ("filter", "for_ios")],
+        )
+    }
+
+    pub fn illust_recommended(&self) -> Option<Value> {
+        self.get(
+            &format!("{}/v1/illust/recommended", self.base_url),
+            &[
+                ("content_type", "illust"),
+                ("include_ranking_label", "true"),
+                ("filter", "for_ios"),
+            ],
+        )
+    }
+}
+
+/// pixiv answers with 400 and an OAuth error, rather than 401, once the
+/// access token has expired
+fn token_expired(code: u16, body: &str) -> bool {
+    code == 401 || (code == 400 && (body.contains("invalid_grant") || body.contains("OAuth")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockApi;
+
+    #[test]
+    fn test_search_target_from_key() {
+        assert_eq!(SearchTarget::from_key("e").as_str(), "exact_match_for_tags");
+        assert_eq!(SearchTarget::from_key("t").as_str(), "title_and_caption");
+        assert_eq!(SearchTarget::from_key("").as_str(), "partial_match_for_tags");
+    }
+
+    #[test]
+    fn test_ranking_mode_from_key() {
+        assert_eq!(RankingMode::from_key("").as_str(), "day");
+        assert_eq!(RankingMode::from_key("w").as_str(), "week");
+        assert_eq!(RankingMode::from_key("m").as_str(), "month");
+        assert_eq!(RankingMode::from_key("r").as_str(), "week_rookie");
+        assert_eq!(RankingMode::from_key("o").as_str(), "week_original");
+    }
+
+    #[test]
+    fn test_restrict() {
+        assert_eq!(Restrict::from_key("2"), Restrict::Private);
+        assert_eq!(Restrict::from_key(""), Restrict::Public);
+        assert_eq!(Restrict::Public.toggle(), Restrict::Private);
+        assert_eq!(Restrict::Private.toggle().as_str(), "public");
+    }
+
+    #[test]
+    fn test_user_bookmarks_illust() {
+        let mock = MockApi::start(|_| (200, r#"{"illusts": [], "next_url": null}"#.to_string()));
+        let api = mock.api();
+        let raw = api.user_bookmarks_illust("2232374", Restrict::Private, None).unwrap();
+        assert_eq!(raw["illusts"].as_array().unwrap().len(), 0);
+        api.user_bookmarks_illust("2232374", Restrict::Public, Some("御坂美琴"));
+        assert_eq!(
+            mock.paths(),
+            [
+                "GET /v1/user/bookmarks/illust?user_id=2232374&restrict=private&filter=for_ios",
+                "GET /v1/user/bookmarks/illust?user_id=2232374&restrict=public&filter=for_ios&tag=%E5%BE%A1%E5%9D%82%E7%BE%8E%E7%90%B4"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_illust_related() {
+        let mock = MockApi::start(|_| (200, r#"{"illusts": [], "next_url": null}"#.to_string()));
+        assert!(mock.api().illust_related("76695217").is_some());
+        assert_eq!(
+            mock.paths(),
+            ["GET /v2/illust/related?illust_id=76695217&filter=for_ios"]
+        );
+    }
+
+    #[test]
+    fn test_refresh_on_expired_token() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = AtomicUsize::new(0);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mock = MockApi::start(move |request| {
+            if request.path == "/auth/token" {
+                let tokens = r#"{"access_token": "fresh", "refresh_token": "rotated", "user": {"id": "2232374"}}"#;
+                (200, tokens.to_string())
+            } else if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                let error = r#"{"error": {"message": "Error occurred at the OAuth process."}}"#;
+                (400, error.to_string())
+            } else {
+                (200, r#"{"illusts": []}"#.to_string())
+            }
+        });
+        let api = mock
+            .api()
+            .with_refresh_token(&format!("{}/auth/token", mock.url), "refresh")
+            .on_new_refresh_token(move |token| sender.send(token.to_string()).unwrap());
+        assert!(api.illust_related("76695217").is_some());
+        assert_eq!(*api.access_token.borrow(), "fresh");
+        assert_eq!(*api.refresh_token.borrow(), "rotated");
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), ["rotated"]);
+        assert_eq!(
+            mock.paths(),
+            [
+                "GET /v2/illust/related?illust_id=76695217&filter=for_ios",
+                "POST /auth/token",
+                "GET /v2/illust/related?illust_id=76695217&filter=for_ios",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_token_expired() {
+        assert!(token_expired(401, ""));
+        assert!(token_expired(400, r#"{"error": "invalid_grant"}"#));
+        assert!(!token_expired(400, r#"{"error": {"message": "Invalid illust_id"}}"#));
+        assert!(!token_expired(404, ""));
+    }
+
+    #[test]
+    fn test_search_sort_from_key() {
+        assert_eq!(SearchSort::from_key("o").as_str(), "date_asc");
+        assert_eq!(SearchSort::from_key("n").as_str(), "date_desc");
+    }
+}
@@ -0,0 +1,218 @@
+//! Logging in with pixiv's OAuth PKCE flow. The user logs in on pixiv's
+//! site, which redirects to a `pixiv://` link with a `code` that is pasted
+//! back here and exchanged for an access token and a refresh token.
+//! Only the refresh token is kept; it is exchanged again on every start
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::Utc;
+use rand_core::{OsRng, RngCore};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::prompt;
+use crate::system::system;
+
+pub const AUTH_URL: &str = "https://oauth.secure.pixiv.net/auth/token";
+const LOGIN_URL: &str = "https://app-api.pixiv.net/web/v1/login";
+const REDIRECT_URI: &str = "https://app-api.pixiv.net/web/v1/users/auth/pixiv/callback";
+const CLIENT_ID: &str = "MOBrBDS8blbauoSck0ZfDbtuzpyT";
+const CLIENT_SECRET: &str = "lsACyCD94FhDUtGTXi3QzcFE2uU1hqtDaKeqrdwj";
+const HASH_SECRET: &str = "28c1fdd170a5204386cb1313c7077b34f83e4aaf4aa829ce78c231e05b0bae2c";
+const USER_AGENT: &str = "PixivAndroidApp/5.0.234 (Android 11; Pixel 5)";
+
+pub struct Tokens {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// The logged in user's ID
+    pub user_id: String,
+}
+
+/// A random secret, and its hash that is sent with the login URL
+pub struct Pkce {
+    pub verifier: String,
+    pub challenge: String,
+}
+
+impl Pkce {
+    pub fn new() -> Pkce {
+        let mut bytes = [0; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let verifier = URL_SAFE_NO_PAD.encode(bytes);
+        Pkce {
+            challenge: challenge(&verifier),
+            verifier,
+        }
+    }
+}
+
+fn challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+pub fn login_url(challenge: &str) -> String {
+    format!(
+        "{}?code_challenge={}&code_challenge_method=S256&client=pixiv-android",
+        LOGIN_URL, challenge
+    )
+}
+
+/// Takes either the code by itself, or the whole link that contains it
+pub fn code_from_input(input: &str) -> Option<String> {
+    let input = input.trim();
+    let code = match input.find("code=") {
+        Some(idx) => input[idx + 5..].split('&').next()?,
+        None => input,
+    };
+    if code.is_empty() || code.contains(|c: char| c.is_whitespace() || c == '/') {
+        None
+    } else {
+        Some(code.to_string())
+    }
+}
+
+/// Older responses nest everything under `response`
+fn parse_tokens(raw: &Value) -> Option<Tokens> {
+    let raw = if raw["response"].is_object() {
+        &raw["response"]
+    } else {
+        raw
+    };
+    let user_id = match &raw["user"]["id"] {
+        Value::String(id) => id.clone(),
+        Value::Number(id) => id.to_string(),
+        _ => String::new(),
+    };
+    Some(Tokens {
+        access_token: raw["access_token"].as_str()?.to_string(),
+        refresh_token: raw["refresh_token"].as_str()?.to_string(),
+        user_id,
+    })
+}
+
+fn token_request(auth_url: &str, form: &[(&str, &str)]) -> Option<Tokens> {
+    let client_time = Utc::now().format("%Y-%m-%dT%H:%M:%S+00:00").to_string();
+    let client_hash = format!(
+        "{:x}",
+        md5::compute(format!("{}{}", client_time, HASH_SECRET))
+    );
+    let mut form = form.to_vec();
+    form.extend(&[
+        ("client_id", CLIENT_ID),
+        ("client_secret", CLIENT_SECRET),
+        ("include_policy", "true"),
+    ]);
+    let response = ureq::post(auth_url)
+        .set("User-Agent", USER_AGENT)
+        .set("X-Client-Time", &client_time)
+        .set("X-Client-Hash", &client_hash)
+        .send_form(&form)
+        .ok()?;
+    parse_tokens(&serde_json::from_str(&response.into_string().ok()?).ok()?)
+}
+
+pub fn exchange_code(auth_url: &str, code: &str, verifier: &str) -> Option<Tokens> {
+    token_request(
+        auth_url,
+        &[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("code_verifier", verifier),
+            ("redirect_uri", REDIRECT_URI),
+        ],
+    )
+}
+
+pub fn refresh(auth_url: &str, refresh_token: &str) -> Option<Tokens> {
+    token_request(
+        auth_url,
+        &[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+        ],
+    )
+}
+
+/// Walks the user through logging in on pixiv's site
+pub fn login_interactively() -> Option<Tokens> {
+    let pkce = Pkce::new();
+    let url = login_url(&pkce.challenge);
+    println!("Please log in to pixiv in your browser, at:\n{}\n", url);
+    let _ = system().open_url(&url);
+    println!("Before logging in, open the browser's developer tools on the network tab.");
+    println!("After logging in, look for a request to pixiv://account/login?code=...");
+    println!("The code only works for a minute, so copy it over quickly.");
+    let code = code_from_input(&prompt::input("Paste the code or the whole link here: "))?;
+    exchange_code(AUTH_URL, &code, &pkce.verifier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockApi;
+
+    const TOKENS: &str = r#"{"access_token": "access", "expires_in": 3600, "refresh_token": "refresh", "user": {"id": "2232374", "name": "koneko"}}"#;
+
+    #[test]
+    fn test_pkce() {
+        // The example in RFC 7636, appendix B
+        assert_eq!(
+            challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+        let pkce = Pkce::new();
+        assert_eq!(pkce.verifier.len(), 43);
+        assert_eq!(pkce.challenge, challenge(&pkce.verifier));
+        assert_ne!(Pkce::new().verifier, pkce.verifier);
+    }
+
+    #[test]
+    fn test_code_from_input() {
+        assert_eq!(
+            code_from_input(" abc-DEF_123 "),
+            Some("abc-DEF_123".to_string())
+        );
+        assert_eq!(
+            code_from_input("pixiv://account/login?code=abc-DEF_123&via=login"),
+            Some("abc-DEF_123".to_string())
+        );
+        assert_eq!(
+            code_from_input("https://app-api.pixiv.net/web/v1/login"),
+            None
+        );
+        assert_eq!(code_from_input(""), None);
+    }
+
+    #[test]
+    fn test_parse_tokens() {
+        let tokens = parse_tokens(&serde_json::from_str(TOKENS).unwrap()).unwrap();
+        assert_eq!(tokens.access_token, "access");
+        assert_eq!(tokens.refresh_token, "refresh");
+        assert_eq!(tokens.user_id, "2232374");
+
+        let nested = serde_json::json!({"response": {"access_token": "a", "refresh_token": "r", "user": {"id": 1}}});
+        assert_eq!(parse_tokens(&nested).unwrap().user_id, "1");
+        assert!(parse_tokens(&serde_json::json!({"has_error": true})).is_none());
+    }
+
+    #[test]
+    fn test_exchange_code_and_refresh() {
+        let mock = MockApi::start(|_| (200, TOKENS.to_string()));
+        let auth_url = format!("{}/auth/token", mock.url);
+        let tokens = exchange_code(&auth_url, "the-code", "the-verifier").unwrap();
+        assert_eq!(tokens.refresh_token, "refresh");
+        refresh(&auth_url, "refresh").unwrap();
+
+        let requests = mock.requests.lock().unwrap();
+        assert_eq!(requests[0].path, "/auth/token");
+        assert!(requests[0].body.starts_with(
+            "grant_type=authorization_code&code=the-code&code_verifier=the-verifier&redirect_uri="
+        ));
+        assert!(requests[0]
+            .body
+            .contains("client_id=MOBrBDS8blbauoSck0ZfDbtuzpyT"));
+        assert!(requests[1]
+            .body
+            .starts_with("grant_type=refresh_token&refresh_token=refresh&client_id="));
+    }
+}
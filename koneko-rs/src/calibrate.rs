@@ -0,0 +1,92 @@
+//! Adjusting the size and spacing of thumbnails on a grid of placeholder
+//! images, laid out the same way as a gallery
+
+use image::{DynamicImage, Rgb, RgbImage};
+
+use crate::config;
+use crate::printer;
+use crate::prompt;
+use crate::pure;
+use crate::renderer;
+use crate::utils;
+
+/// Image width and height in cells, and the spacing between images
+type Layout = (i32, i32, i32);
+
+/// Returns the layout to save, or None if it was cancelled
+pub fn calibrate() -> Option<Layout> {
+    let (width, spacing) = config::width_padding("width", "x");
+    let (height, _) = config::width_padding("height", "x");
+    let mut layout = (width, height, spacing);
+    loop {
+        show_samples(layout);
+        println!(
+            "image_width = {}; image_height = {}; image_x_spacing = {}",
+            layout.0, layout.1, layout.2
+        );
+        println!("[h/l] narrower/wider; [k/j] shorter/taller; [-/+] less/more spacing;");
+        println!("Press enter to save, or [q] to cancel");
+        let command = prompt::input("Enter a calibration command: ");
+        if command.is_empty() {
+            return Some(layout);
+        }
+        for key in command.chars() {
+            if key == 'q' {
+                return None;
+            }
+            layout = apply_key(layout, key);
+        }
+    }
+}
+
+fn apply_key(layout: Layout, key: char) -> Layout {
+    let (width, height, spacing) = layout;
+    match key {
+        'h' => ((width - 1).max(1), height, spacing),
+        'l' => (width + 1, height, spacing),
+        'k' => (width, (height - 1).max(1), spacing),
+        'j' => (width, height + 1, spacing),
+        '-' => (width, height, (spacing - 1).max(0)),
+        '+' | '=' => (width, height, spacing + 1),
+        _ => layout,
+    }
+}
+
+/// A square with a different color for every number, like a thumbnail
+fn sample(number: usize) -> DynamicImage {
+    let hue = (number * 47 % 256) as u8;
+    DynamicImage::ImageRgb8(RgbImage::from_fn(64, 64, |x, y| {
+        Rgb([hue, (x * 4) as u8, (y * 4) as u8])
+    }))
+}
+
+fn show_samples((width, height, spacing): Layout) {
+    let renderer = renderer::renderer();
+    renderer.clear();
+    let xcoords = pure::xcoords(utils::term_width(), width, spacing, 0);
+    // Leaves room for the text below
+    let rows = utils::term_height().saturating_sub(4);
+    let ycoords = pure::ycoords(rows, height, spacing);
+    for (row, y) in ycoords.iter().enumerate() {
+        for (col, x) in xcoords.iter().enumerate() {
+            renderer.show_image(&sample(row * xcoords.len() + col), (width, height), *x, *y);
+        }
+    }
+    printer::move_cursor_xy(0, rows as i32);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_key() {
+        assert_eq!(apply_key((18, 8, 2), 'l'), (19, 8, 2));
+        assert_eq!(apply_key((18, 8, 2), 'j'), (18, 9, 2));
+        assert_eq!(apply_key((18, 8, 2), '+'), (18, 8, 3));
+        assert_eq!(apply_key((1, 1, 0), 'h'), (1, 1, 0));
+        assert_eq!(apply_key((1, 1, 0), 'k'), (1, 1, 0));
+        assert_eq!(apply_key((1, 1, 0), '-'), (1, 1, 0));
+        assert_eq!(apply_key((18, 8, 2), 'x'), (18, 8, 2));
+    }
+}
@@ -1,51 +1,183 @@
-pub const RED: &str = "\x1b[31m";
-pub const MAGENTA: &str = "\x1b[35m";
-pub const BLUE: &str = "\x1b[34m";
-pub const RESET: &str = "\x1b[39m";
+//! The colors of key hints in the help text and of labels, taken from the
+//! `[theme]` section of the config. Nothing is colored if `NO_COLOR` is set
+//! or stdout is not a terminal
+
+use std::env;
+use std::io::{self, IsTerminal};
+use std::sync::Mutex;
+
+use crate::config;
+
+const NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+/// The foreground escape code for a named color, a 256-color number, or `#rrggbb`
+pub fn parse_color(spec: &str) -> Option<String> {
+    let spec = spec.trim().to_lowercase();
+    if let Some(hex) = spec.strip_prefix('#') {
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let channel = |idx: usize| u8::from_str_radix(&hex[idx..idx + 2], 16).unwrap();
+        return Some(format!(
+            "\x1b[38;2;{};{};{}m",
+            channel(0),
+            channel(2),
+            channel(4)
+        ));
+    }
+    if let Ok(number) = spec.parse::<u8>() {
+        return Some(format!("\x1b[38;5;{}m", number));
+    }
+    if spec == "default" {
+        return Some("\x1b[39m".to_string());
+    }
+    let (base, name) = match spec.strip_prefix("bright_") {
+        Some(name) => (90, name),
+        None => (30, spec.as_str()),
+    };
+    let code = NAMES.iter().position(|known| *known == name)?;
+    Some(format!("\x1b[{}m", base + code))
+}
+
+/// `NO_COLOR` turns colors off if it is set to anything, see https://no-color.org
+fn colors_enabled() -> bool {
+    env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) && io::stdout().is_terminal()
+}
+
+/// Escape codes for each part of a key hint, all empty if colors are off
+#[derive(Clone)]
+pub struct Theme {
+    /// Brackets around keys, and the selected label
+    pub bracket: String,
+    /// The keys themselves
+    pub key: String,
+    /// Coordinates and page counts
+    pub number: String,
+    pub reset: String,
+}
+
+/// The theme in use, read from the config on first use; see `forget_theme`
+static CURRENT: Mutex<Option<Theme>> = Mutex::new(None);
+
+/// Makes the next `Theme::current` read the config again, after it changed
+pub fn forget_theme() {
+    *CURRENT.lock().unwrap() = None;
+}
+
+impl Theme {
+    pub fn current() -> Theme {
+        CURRENT
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Theme::from_config)
+            .clone()
+    }
+
+    fn from_config() -> Theme {
+        if !colors_enabled() {
+            return Theme::plain();
+        }
+        Theme {
+            bracket: config::theme_color_config("bracket"),
+            key: config::theme_color_config("key"),
+            number: config::theme_color_config("number"),
+            reset: "\x1b[0m".to_string(),
+        }
+    }
+
+    pub fn plain() -> Theme {
+        Theme {
+            bracket: String::new(),
+            key: String::new(),
+            number: String::new(),
+            reset: String::new(),
+        }
+    }
+
+    fn nth(&self) -> String {
+        format!(
+            "{}[{}n{}]{}",
+            self.bracket, self.number, self.bracket, self.reset
+        )
+    }
+
+    fn coords(&self) -> String {
+        format!(
+            "{red}{{{blue}x{red}}}{{{blue}y{red}}}{reset}",
+            //    ^^            ^^^^            ^^
+            //    '{'           '}{'            '}'
+            red = self.bracket,
+            blue = self.number,
+            reset = self.reset
+        )
+    }
+
+    fn letters_with_brackets(&self, letters: &str) -> String {
+        format!(
+            "{red}[{}{}{red}]{}",
+            self.key,
+            letters,
+            self.reset,
+            red = self.bracket
+        )
+    }
+
+    fn letter_with_coords(&self, letter: char) -> String {
+        format!(
+            "{red}[{}{}{red}]{}{}",
+            self.key,
+            letter,
+            self.nth(),
+            self.reset,
+            red = self.bracket
+        )
+    }
+
+    fn two_letter_with_coords(&self, letter: char) -> String {
+        format!(
+            "{red}[{magenta}{}{reset}{}|{magenta}{}{}{red}]{reset}",
+            letter.to_lowercase(),
+            self.coords(),
+            letter.to_uppercase(),
+            self.nth(),
+            red = self.bracket,
+            magenta = self.key,
+            reset = self.reset
+        )
+    }
+
+    /// A label such as `[3]`, marking the current page
+    pub fn selected(&self, label: &str) -> String {
+        format!("{}[{}]{}", self.bracket, label, self.reset)
+    }
+}
 
 #[allow(non_snake_case)]
 pub fn BLUE_N() -> String {
-    format!("{}[{}n{}]{}", RED, BLUE, RED, RESET)
+    Theme::current().nth()
 }
 
 #[allow(non_snake_case)]
 fn COORDS() -> String {
-    format!(
-        "{red}{{{blue}x{red}}}{{{blue}y{red}}}{reset}",
-        //    ^^            ^^^^            ^^
-        //    '{'           '}{'            '}'
-        red = RED,
-        blue = BLUE,
-        reset = RESET
-    )
+    Theme::current().coords()
 }
 
 fn letter_with_brackets(letter: char) -> String {
-    format!("{red}[{}{}{red}]{}", MAGENTA, letter, RESET, red = RED)
+    Theme::current().letters_with_brackets(&letter.to_string())
+}
+
+fn letters_with_brackets(letters: &str) -> String {
+    Theme::current().letters_with_brackets(letters)
 }
 
 fn letter_with_coords(letter: char) -> String {
-    format!(
-        "{red}[{}{}{red}]{}{}",
-        MAGENTA,
-        letter,
-        BLUE_N(),
-        RESET,
-        red = RED
-    )
+    Theme::current().letter_with_coords(letter)
 }
 
 fn two_letter_with_coords(letter: char) -> String {
-    format!(
-        "{red}[{magenta}{}{reset}{}|{magenta}{}{}{red}]{reset}",
-        letter.to_lowercase(),
-        COORDS(),
-        letter.to_uppercase(),
-        BLUE_N(),
-        red = RED,
-        magenta = MAGENTA,
-        reset = RESET
-    )
+    Theme::current().two_letter_with_coords(letter)
 }
 
 pub fn n() -> String {
@@ -84,10 +216,74 @@ pub fn f() -> String {
     letter_with_brackets('f')
 }
 
+pub fn s_() -> String {
+    letter_with_brackets('s')
+}
+
+pub fn u_() -> String {
+    letter_with_brackets('u')
+}
+
+pub fn shift_f() -> String {
+    letter_with_brackets('F')
+}
+
+pub fn shift_u() -> String {
+    letter_with_brackets('U')
+}
+
+pub fn v() -> String {
+    letter_with_brackets('v')
+}
+
+pub fn a_() -> String {
+    letter_with_brackets('a')
+}
+
+pub fn t() -> String {
+    letter_with_brackets('t')
+}
+
+pub fn plus() -> String {
+    letter_with_brackets('+')
+}
+
+pub fn minus() -> String {
+    letter_with_brackets('-')
+}
+
+pub fn zero() -> String {
+    letter_with_brackets('0')
+}
+
+pub fn hjkl() -> String {
+    letters_with_brackets("hjkl")
+}
+
+pub fn j() -> String {
+    letter_with_brackets('j')
+}
+
+pub fn k() -> String {
+    letter_with_brackets('k')
+}
+
+pub fn slash() -> String {
+    letter_with_brackets('/')
+}
+
 pub fn i() -> String {
     letter_with_coords('i')
 }
 
+pub fn f_n() -> String {
+    letter_with_coords('f')
+}
+
+pub fn u_n() -> String {
+    letter_with_coords('u')
+}
+
 pub fn a() -> String {
     two_letter_with_coords('a')
 }
@@ -100,6 +296,14 @@ pub fn d() -> String {
     two_letter_with_coords('d')
 }
 
+pub fn s() -> String {
+    two_letter_with_coords('s')
+}
+
+pub fn u() -> String {
+    two_letter_with_coords('u')
+}
+
 pub fn base1() -> [String; 8] {
     [
         COORDS(),
@@ -125,3 +329,46 @@ pub fn base2() -> [String; 8] {
         "uit (with confirmation); ".to_string(),
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn colored() -> Theme {
+        Theme {
+            bracket: "<b>".to_string(),
+            key: "<k>".to_string(),
+            number: "<n>".to_string(),
+            reset: "<r>".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("red").unwrap(), "\x1b[31m");
+        assert_eq!(parse_color(" Magenta ").unwrap(), "\x1b[35m");
+        assert_eq!(parse_color("bright_blue").unwrap(), "\x1b[94m");
+        assert_eq!(parse_color("default").unwrap(), "\x1b[39m");
+        assert_eq!(parse_color("208").unwrap(), "\x1b[38;5;208m");
+        assert_eq!(parse_color("#FF8000").unwrap(), "\x1b[38;2;255;128;0m");
+        assert_eq!(parse_color("256"), None);
+        assert_eq!(parse_color("#ff80"), None);
+        assert_eq!(parse_color("#+f8000"), None);
+        assert_eq!(parse_color("bright_orange"), None);
+        assert_eq!(parse_color(""), None);
+    }
+
+    #[test]
+    fn test_theme() {
+        let plain = Theme::plain();
+        assert_eq!(plain.letters_with_brackets("hjkl"), "[hjkl]");
+        assert_eq!(plain.letter_with_coords('i'), "[i][n]");
+        assert_eq!(plain.two_letter_with_coords('d'), "[d{x}{y}|D[n]]");
+        assert_eq!(plain.selected("3"), "[3]");
+
+        let theme = colored();
+        assert_eq!(theme.letters_with_brackets("n"), "<b>[<k>n<b>]<r>");
+        assert_eq!(theme.nth(), "<b>[<n>n<b>]<r>");
+        assert_eq!(theme.coords(), "<b>{<n>x<b>}{<n>y<b>}<r>");
+    }
+}
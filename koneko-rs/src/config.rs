@@ -1,89 +1,532 @@
+use std::env;
+use std::fs;
 use std::io;
 use std::path::Path;
+use std::process;
 use std::str::FromStr;
 use std::io::prelude::*;
-use std::fs::{self, File};
-use std::process::Command;
+use std::fs::File;
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
 
+use crate::auth;
+use crate::calibrate;
+use crate::colors;
+use crate::files;
+use crate::prompt;
 use crate::pure;
+use crate::secret;
+use crate::system::system;
 use crate::utils;
+use crate::filter::Filter;
+use crate::renderer::Backend;
 
 const CONFIGPATH: &str = "~/.config/koneko/config.ini";
 
 fn read_raw() -> Option<String> {
-    let mut f = File::open(CONFIGPATH).ok()?;
+    let mut f = File::open(pure::expand_home(Path::new(CONFIGPATH))).ok()?;
     let mut result = String::new();
     f.read_to_string(&mut result).ok()?;
 
-    Some(result.to_lowercase())
+    Some(result)
+}
+
+fn section_in_raw(raw: &str, section_name: &str) -> Option<String> {
+    let section_head = format!("[{}]", section_name);
+    let mut lines = raw
+        .lines()
+        .skip_while(|line| !line.trim().eq_ignore_ascii_case(&section_head));
+    lines.next()?;
+    let section: Vec<&str> = lines
+        .take_while(|line| !line.trim_start().starts_with('['))
+        .collect();
+    Some(section.join("\n"))
+}
+
+fn setting_in_section(section: &str, setting_name: &str) -> Option<String> {
+    section.lines().find_map(|line| {
+        let mut kv = line.splitn(2, '=');
+        if kv.next()?.trim().eq_ignore_ascii_case(setting_name) {
+            Some(kv.next()?.trim().to_string())
+        } else {
+            None
+        }
+    })
 }
 
 fn get_section(section_name: &str) -> Option<String> {
-    let file: String = read_raw()?;
-    let section_head = file
-        .split(&format!("[{}]", section_name.to_lowercase()))
-        .last()?;
-    let section = section_head.split('[').next()?.trim();
-    Some(section.to_string())
+    section_in_raw(&read_raw()?, section_name)
 }
 
-fn get_setting(section_name: &str, setting_name: &str) -> Option<String> {
-    let section = get_section(&section_name.to_lowercase())?;
-    let startpos = section.find(&setting_name.to_lowercase())?;
-    let endpos = section.get(startpos..)?.find('\n')?;
-    let kv_vec: &Vec<&str> = &section[startpos..endpos].split('=').collect();
+/// Where the effective value of a setting comes from, from highest priority
+#[derive(Debug, PartialEq)]
+pub enum Source {
+    Flag,
+    Env,
+    File,
+    Default,
+}
+
+impl Source {
+    fn describe(&self, section_name: &str, setting_name: &str) -> String {
+        match self {
+            Source::Flag => "--set".to_string(),
+            Source::Env => env_var_name(section_name, setting_name),
+            Source::File => CONFIGPATH.to_string(),
+            Source::Default => "default".to_string(),
+        }
+    }
+}
+
+/// A `--set section.key=value` flag
+#[derive(Debug, PartialEq)]
+pub struct Override {
+    pub section: String,
+    pub key: String,
+    pub value: String,
+}
+
+/// Set once at startup from the command line, before any setting is read
+static OVERRIDES: OnceLock<Vec<Override>> = OnceLock::new();
+
+pub fn set_overrides(overrides: Vec<Override>) {
+    let _ = OVERRIDES.set(overrides);
+}
+
+fn parse_override(arg: &str) -> Option<Override> {
+    let (name, value) = arg.split_once('=')?;
+    let (section, key) = name.trim().split_once('.')?;
+    if section.is_empty() || key.is_empty() {
+        return None;
+    }
+    Some(Override {
+        section: section.to_string(),
+        key: key.to_string(),
+        value: value.trim().to_string(),
+    })
+}
+
+/// Takes out every `--set section.key=value` and returns the other arguments,
+/// or a message if a flag is malformed
+pub fn take_overrides(args: Vec<String>) -> Result<(Vec<Override>, Vec<String>), String> {
+    let mut overrides = vec![];
+    let mut rest = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let value = if arg == "--set" {
+            args.next()
+                .ok_or_else(|| "--set needs a section.key=value".to_string())?
+        } else if let Some(value) = arg.strip_prefix("--set=") {
+            value.to_string()
+        } else {
+            rest.push(arg);
+            continue;
+        };
+        let setting = parse_override(&value)
+            .ok_or_else(|| format!("Invalid --set {}, expected section.key=value", value))?;
+        if !SETTINGS.iter().any(|known| {
+            known.section.eq_ignore_ascii_case(&setting.section)
+                && known.key.eq_ignore_ascii_case(&setting.key)
+        }) {
+            println!(
+                "Warning: {}.{} is not a known setting",
+                setting.section, setting.key
+            );
+        }
+        overrides.push(setting);
+    }
+    Ok((overrides, rest))
+}
+
+fn env_var_name(section_name: &str, setting_name: &str) -> String {
+    format!(
+        "KONEKO_{}_{}",
+        section_name.to_uppercase(),
+        setting_name.to_uppercase()
+    )
+}
+
+/// The last matching flag wins, like repeating a flag usually does
+fn resolve(
+    section_name: &str,
+    setting_name: &str,
+    overrides: &[Override],
+    env: impl Fn(&str) -> Option<String>,
+    raw: Option<&str>,
+) -> Option<(String, Source)> {
+    let flag = overrides.iter().rev().find(|setting| {
+        setting.section.eq_ignore_ascii_case(section_name)
+            && setting.key.eq_ignore_ascii_case(setting_name)
+    });
+    if let Some(flag) = flag {
+        return Some((flag.value.clone(), Source::Flag));
+    }
+    if let Some(value) = env(&env_var_name(section_name, setting_name)) {
+        return Some((value, Source::Env));
+    }
+    let section = section_in_raw(raw?, section_name)?;
+    Some((setting_in_section(&section, setting_name)?, Source::File))
+}
+
+/// The setting from the flags, the environment or the config, in that order
+fn lookup_setting(section_name: &str, setting_name: &str) -> Option<(String, Source)> {
+    resolve(
+        section_name,
+        setting_name,
+        OVERRIDES
+            .get()
+            .map_or(&[], |overrides| overrides.as_slice()),
+        |name| env::var(name).ok(),
+        read_raw().as_deref(),
+    )
+}
 
-    Some(kv_vec[1].to_string())
+fn get_setting(section_name: &str, setting_name: &str) -> Option<String> {
+    lookup_setting(section_name, setting_name).map(|(value, _)| value)
 }
 
 fn parse_setting<T: FromStr>(section: &str, setting: &str, fallback: T) -> T {
     match get_setting(section, setting) {
-        Some(b) => b.parse::<T>().unwrap_or(fallback),
+        Some(b) => b.to_lowercase().parse::<T>().unwrap_or(fallback),
         _ => fallback,
     }
 }
 
+pub struct Setting {
+    pub section: &'static str,
+    pub key: &'static str,
+    pub default: &'static str,
+    pub comment: &'static str,
+}
+
+/// Every setting that has a default. The config written on first run is
+/// generated from this, so the defaults only live here
+pub const SETTINGS: &[Setting] = &[
+    Setting {
+        section: "lscat",
+        key: "renderer",
+        default: "auto",
+        comment: "How images are drawn: auto, kitty, sixel, iterm2 or blocks.\n\
+                  auto picks one from $TERM and $TERM_PROGRAM",
+    },
+    Setting {
+        section: "lscat",
+        key: "image_width",
+        default: "18",
+        comment: "Width of a thumbnail, in columns",
+    },
+    Setting {
+        section: "lscat",
+        key: "image_height",
+        default: "8",
+        comment: "Height of a thumbnail, in rows",
+    },
+    Setting {
+        section: "lscat",
+        key: "image_x_spacing",
+        default: "2",
+        comment: "Spacing between thumbnails, in columns and rows",
+    },
+    Setting {
+        section: "lscat",
+        key: "page_spacing",
+        default: "23",
+        comment: "Blank lines printed between pages of thumbnails",
+    },
+    Setting {
+        section: "lscat",
+        key: "gallery_print_spacing",
+        default: "9,17,17,17,17",
+        comment: "Spaces before each column number and label in the gallery",
+    },
+    Setting {
+        section: "lscat",
+        key: "users_print_name_xcoord",
+        default: "18",
+        comment: "Column where the artist's name starts in the user list",
+    },
+    Setting {
+        section: "lscat",
+        key: "images_x_spacing",
+        default: "2",
+        comment: "Spacing between the previews in the user list, in columns",
+    },
+    Setting {
+        section: "lscat",
+        key: "image_thumbnail_size",
+        default: "310",
+        comment: "Size of thumbnails in pixels, when the terminal does not report its cell size",
+    },
+    Setting {
+        section: "misc",
+        key: "text_only",
+        default: "false",
+        comment: "Show galleries and user lists as text, without downloading thumbnails",
+    },
+    Setting {
+        section: "misc",
+        key: "print_info",
+        default: "true",
+        comment: "Print the title and artist below each image",
+    },
+    Setting {
+        section: "misc",
+        key: "convert_webp_to_png",
+        default: "false",
+        comment: "Convert WebP images to png when downloading them",
+    },
+    Setting {
+        section: "misc",
+        key: "download_template",
+        default: "~/Downloads/{id}_p{page}.{ext}",
+        comment: "Where images are downloaded to, with these replaced by the post's details:\n{artist_name} {artist_id} {date} {title} {id} {page} {ext}\nFor example ~/Pictures/pixiv/{artist_name}_{artist_id}/{date}_{title}_{id}_p{page}.{ext}",
+    },
+    Setting {
+        section: "misc",
+        key: "browser_command",
+        default: "",
+        comment: "Command that opens links, where %s is the link, or it goes at the end\nIf empty, the commands in $BROWSER are tried, and then xdg-open",
+    },
+    Setting {
+        section: "misc",
+        key: "image_viewer_command",
+        default: "",
+        comment: "Command that opens downloaded images, like browser_command\nIf empty, xdg-open is used",
+    },
+    Setting {
+        section: "theme",
+        key: "bracket",
+        default: "red",
+        comment: "Colors are a name like red or bright_red, a number from 0 to 255, or #rrggbb\nBrackets around the keys in help text, and the selected label",
+    },
+    Setting {
+        section: "theme",
+        key: "key",
+        default: "magenta",
+        comment: "The keys in help text",
+    },
+    Setting {
+        section: "theme",
+        key: "number",
+        default: "blue",
+        comment: "Coordinates and page counts",
+    },
+    Setting {
+        section: "filter",
+        key: "blocked_tags",
+        default: "",
+        comment: "Posts with any of these tags are hidden, separated by commas\n\
+                  Translated tags count too, and case does not matter",
+    },
+    Setting {
+        section: "filter",
+        key: "blocked_artists",
+        default: "",
+        comment: "IDs of artists whose posts and profiles are hidden, separated by commas",
+    },
+    Setting {
+        section: "filter",
+        key: "max_x_restrict",
+        default: "2",
+        comment: "0 shows only all-ages posts, 1 also shows R-18, 2 also shows R-18G",
+    },
+    Setting {
+        section: "filter",
+        key: "hide_ai_generated",
+        default: "false",
+        comment: "Hide posts marked as AI-generated",
+    },
+    Setting {
+        section: "experimental",
+        key: "image_mode_previews",
+        default: "false",
+        comment: "Show a strip of the other pages below a multi-page post",
+    },
+    Setting {
+        section: "experimental",
+        key: "image_mode_text_offset",
+        default: "4",
+        comment: "Rows left below the image for the page previews and the prompt",
+    },
+];
+
+fn default_setting(section: &str, key: &str) -> &'static str {
+    SETTINGS
+        .iter()
+        .find(|setting| setting.section == section && setting.key == key)
+        .map(|setting| setting.default)
+        .unwrap_or_else(|| panic!("{}.{} has no default", section, key))
+}
+
+/// Every setting with its effective value and where it comes from
+pub fn show_config() {
+    for name in default_section_names() {
+        println!("[{}]", name);
+        for setting in SETTINGS.iter().filter(|setting| setting.section == name) {
+            let (value, source) = lookup_setting(name, setting.key)
+                .unwrap_or_else(|| (setting.default.to_string(), Source::Default));
+            // Still valid ini, so it can be pasted into the config
+            let line = format!("{} = {}", setting.key, value);
+            println!("{:<42} # {}", line, source.describe(name, setting.key));
+        }
+        println!();
+    }
+}
+
+/// Why a value cannot be used, judging the type of the setting by its default
+fn invalid_value(setting: &Setting, raw: &str) -> Option<String> {
+    let value = raw.to_lowercase();
+    let (valid, expected) = if setting.section == "theme" {
+        (
+            colors::parse_color(&value).is_some(),
+            "a color name, a number from 0 to 255, or #rrggbb",
+        )
+    } else if setting.key == "download_template" {
+        (
+            // Checked as written, as placeholders are case sensitive
+            pure::unknown_placeholders(raw).is_empty(),
+            "a path with only the placeholders {artist_name} {artist_id} {date} {title} {id} {page} {ext}",
+        )
+    } else if setting.key == "max_x_restrict" {
+        (matches!(value.as_str(), "0" | "1" | "2"), "0, 1 or 2")
+    } else if setting.key == "blocked_artists" {
+        (
+            value.split(',').all(|id| pure::str_is_digit(id.trim())),
+            "artist IDs separated by commas",
+        )
+    } else if setting.key == "renderer" {
+        (
+            value == "auto" || Backend::from_name(&value).is_some(),
+            "auto, kitty, sixel, iterm2 or blocks",
+        )
+    } else if setting.default.parse::<bool>().is_ok() {
+        (value.parse::<bool>().is_ok(), "true or false")
+    } else if setting.default.parse::<i32>().is_ok() {
+        (value.parse::<i32>().is_ok(), "a number")
+    } else if setting.default.contains(',') {
+        (
+            value.split(',').all(|x| x.trim().parse::<i32>().is_ok()),
+            "numbers separated by commas",
+        )
+    } else {
+        (true, "")
+    };
+    if valid {
+        None
+    } else {
+        Some(format!(
+            "{}.{} = {} should be {}; using {}",
+            setting.section, setting.key, raw, expected, setting.default
+        ))
+    }
+}
+
+/// Every setting that falls back to its default because it cannot be parsed
+pub fn config_errors() -> Vec<String> {
+    if read_raw().is_none() {
+        return vec![format!("Could not read {}; using the defaults", CONFIGPATH)];
+    }
+    SETTINGS
+        .iter()
+        .filter_map(|setting| {
+            let (value, _) = lookup_setting(setting.section, setting.key)?;
+            invalid_value(setting, &value)
+        })
+        .collect()
+}
+
+fn config_modified() -> Option<SystemTime> {
+    fs::metadata(pure::expand_home(Path::new(CONFIGPATH)))
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+/// When the config was last modified, as of the last check
+static LAST_MODIFIED: Mutex<Option<SystemTime>> = Mutex::new(None);
+
+/// Settings are read from the file every time, except the cached theme which is
+/// dropped here, so the new ones are used as soon as this notices a change; the
+/// screen only needs to be drawn again
+pub fn config_changed() -> bool {
+    let modified = config_modified();
+    let mut last_modified = LAST_MODIFIED.lock().unwrap();
+    if *last_modified == modified {
+        return false;
+    }
+    *last_modified = modified;
+    colors::forget_theme();
+    true
+}
+
+/// Starts watching from the config as it is now
+pub fn watch_config() {
+    *LAST_MODIFIED.lock().unwrap() = config_modified();
+}
+
+/// `parse_setting`, falling back to the default in `SETTINGS`
+fn setting<T: FromStr>(section: &str, key: &str) -> T {
+    let default = default_setting(section, key);
+    parse_setting(
+        section,
+        key,
+        default
+            .parse()
+            .unwrap_or_else(|_| panic!("Invalid default for {}.{}", section, key)),
+    )
+}
+
+/// Like `setting`, for a string whose case matters, like a command
+fn raw_setting(section: &str, key: &str) -> String {
+    get_setting(section, key).unwrap_or_else(|| default_setting(section, key).to_string())
+}
+
 pub fn check_image_preview() -> bool {
-    parse_setting("experimental", "image_mode_previews", false)
+    setting("experimental", "image_mode_previews")
+}
+
+/// Shows galleries and user lists as text, without downloading any thumbnails
+pub fn text_only_config() -> bool {
+    setting("misc", "text_only")
+}
+
+/// Images saved to Downloads in WebP are converted to png
+pub fn webp_to_png_config() -> bool {
+    setting("misc", "convert_webp_to_png")
 }
 
 pub fn check_print_info() -> bool {
-    parse_setting("misc", "print_info", true)
+    setting("misc", "print_info")
 }
 
-fn width_padding(side: &str, dimension: &str, fallbacks: (i32, i32)) -> (i32, i32) {
+pub fn width_padding(side: &str, dimension: &str) -> (i32, i32) {
     (
-        parse_setting("lscat", &format!("image_{}", side), fallbacks.0),
-        parse_setting(
-            "lscat",
-            &format!("image_{}_spacing", dimension),
-            fallbacks.1,
-        ),
+        setting("lscat", &format!("image_{}", side)),
+        setting("lscat", &format!("image_{}_spacing", dimension)),
     )
 }
 
 pub fn ncols_config() -> i32 {
-    let (width, padding) = width_padding("width", "x", (18, 2));
+    let (width, padding) = width_padding("width", "x");
     pure::ncols(utils::term_width(), width, padding)
 }
 
 pub fn nrows_config() -> i32 {
-    let (height, padding) = width_padding("height", "x", (8, 2));
+    let (height, padding) = width_padding("height", "x");
     pure::nrows(utils::term_height(), height, padding)
 }
 
 pub fn xcoords_config(offset: i32) -> Vec<i32> {
-    let (width, padding) = width_padding("width", "x", (18, 2));
+    let (width, padding) = width_padding("width", "x");
     pure::xcoords(utils::term_width(), width, padding, offset)
 }
 
 pub fn ycoords_config() -> Vec<i32> {
-    let (height, padding) = width_padding("height", "x", (8, 2));
+    let (height, padding) = width_padding("height", "x");
     pure::ycoords(utils::term_height(), height, padding)
 }
 
 pub fn gallery_page_spacing_config() -> i32 {
-    parse_setting("lscat", "page_spacing", 23)
+    setting("lscat", "page_spacing")
 }
 
 pub fn users_page_spacing_config() -> i32 {
@@ -91,113 +534,593 @@ pub fn users_page_spacing_config() -> i32 {
 }
 
 pub fn thumbnail_size_config() -> i32 {
-    parse_setting("lscat", "image_thumbnail_size", 310)
+    setting("lscat", "image_thumbnail_size")
 }
 
 pub fn get_gen_users_settings() -> (i32, i32) {
     (
-        parse_setting("lscat", "users_print_name_xcoord", 18),
-        parse_setting("lscat", "images_x_spacing", 2),
+        setting("lscat", "users_print_name_xcoord"),
+        setting("lscat", "images_x_spacing"),
     )
 }
 
+/// One of auto, kitty, sixel, iterm2 or blocks
+pub fn renderer_config() -> String {
+    setting("lscat", "renderer")
+}
+
 pub fn image_text_offset() -> i32 {
-    parse_setting("experimental", "image_mode_text_offset", 4)
+    setting("experimental", "image_mode_text_offset")
 }
 
 pub fn gallery_print_spacing_config() -> Vec<i32> {
-    let setting = get_setting("lscat", "gallery_print_spacing")
-        .unwrap_or_else(|| "9,17,17,17,17".to_string());
-    // TODO: if there is any parsing error, return the default
-    setting
-        .split(',')
-        .map(|x| x.parse::<i32>().unwrap())
-        .collect()
+    let parse = |setting: &str| -> Option<Vec<i32>> {
+        setting.split(',').map(|x| x.trim().parse().ok()).collect()
+    };
+    get_setting("lscat", "gallery_print_spacing")
+        .and_then(|setting| parse(&setting))
+        .unwrap_or_else(|| parse(default_setting("lscat", "gallery_print_spacing")).unwrap())
+}
+
+pub fn download_template_config() -> String {
+    let template = raw_setting("misc", "download_template");
+    if pure::unknown_placeholders(&template).is_empty() {
+        template
+    } else {
+        default_setting("misc", "download_template").to_string()
+    }
+}
+
+pub fn browser_command_config() -> String {
+    raw_setting("misc", "browser_command")
+}
+
+pub fn image_viewer_command_config() -> String {
+    raw_setting("misc", "image_viewer_command")
+}
+
+/// The escape code of a color in the theme
+pub fn theme_color_config(key: &str) -> String {
+    let value: String = setting("theme", key);
+    colors::parse_color(&value)
+        .unwrap_or_else(|| colors::parse_color(default_setting("theme", key)).unwrap())
+}
+
+fn comma_list(section: &str, setting: &str) -> Vec<String> {
+    match get_setting(section, setting) {
+        Some(list) => list
+            .split(',')
+            .map(|x| x.trim().to_lowercase())
+            .filter(|x| !x.is_empty())
+            .collect(),
+        None => vec![],
+    }
+}
+
+pub fn filter_config() -> Filter {
+    let max_x_restrict: i64 = setting("filter", "max_x_restrict");
+    Filter {
+        blocked_tags: comma_list("filter", "blocked_tags"),
+        blocked_artists: comma_list("filter", "blocked_artists"),
+        max_x_restrict: if (0..=2).contains(&max_x_restrict) {
+            max_x_restrict
+        } else {
+            default_setting("filter", "max_x_restrict").parse().unwrap()
+        },
+        hide_ai_generated: setting("filter", "hide_ai_generated"),
+    }
 }
 
 pub struct Credentials {
-    pub username: String,
-    pub password: String,
+    /// Exchanged for an access token on every start, see `auth`
+    pub refresh_token: String,
     pub your_id: String,
+    /// Unlocks the refresh token saved by `secret`; None if it is in the config
+    pub passphrase: Option<String>,
 }
 
-pub fn credentials_from_config() -> Credentials {
-    Credentials {
-        username: get_setting("Credentials", "username").unwrap(),
-        password: get_setting("Credentials", "password").unwrap(),
-        your_id: get_setting("Credentials", "ID").unwrap(),
+/// None if the config has no refresh token, such as a config from
+/// before pixiv stopped accepting passwords
+pub fn credentials_from_config() -> Option<Credentials> {
+    let (refresh_token, passphrase) = match get_setting("Credentials", "refresh_token") {
+        Some(refresh_token) => (refresh_token, None),
+        None if secret::secret_exists() => unlock_refresh_token(),
+        None => return None,
+    };
+    Some(Credentials {
+        refresh_token,
+        your_id: get_setting("Credentials", "ID").unwrap_or_default(),
+        passphrase,
+    })
+}
+
+/// The refresh token is not in the config if it was encrypted with a passphrase,
+/// which is returned with it so that a new refresh token can be saved the same way
+fn unlock_refresh_token() -> (String, Option<String>) {
+    for _ in 0..3 {
+        let passphrase = prompt::hidden_input("Enter the passphrase to unlock your login: ");
+        if let Some(refresh_token) = secret::read_secret(&passphrase) {
+            return (refresh_token, Some(passphrase));
+        }
+        println!("Wrong passphrase!");
     }
+    println!("Failed to unlock the login!");
+    process::exit(1)
 }
 
 pub fn begin_config() -> Credentials {
-    Command::new("clear").spawn();
-    if Path::new(CONFIGPATH).exists() {
-        credentials_from_config()
-    } else {
-        init_config()
+    system().clear();
+    if !pure::expand_home(Path::new(CONFIGPATH)).exists() {
+        return init_config();
     }
+    credentials_from_config().unwrap_or_else(ask_login)
 }
 
+/// The first run: logs in, writes every setting at its default, picks
+/// how images are drawn, and optionally calibrates the thumbnail layout
 fn init_config() -> Credentials {
-    let (username, password) = ask_credentials();
-    let your_id = ask_your_id();
+    println!("Welcome to koneko! Let's set up {}\n", CONFIGPATH);
+    let creds = ask_login();
+    update_config(add_default_sections);
+    save_setting("lscat", "renderer", &ask_renderer());
+
+    println!("\nDo you want to adjust the size and spacing of thumbnails now? [y/N]");
+    let mut ans = String::new();
+    io::stdin().read_line(&mut ans).unwrap();
+    if matches!(ans.trim(), "y" | "Y") {
+        if let Some((width, height, spacing)) = calibrate::calibrate() {
+            save_setting("lscat", "image_width", &width.to_string());
+            save_setting("lscat", "image_height", &height.to_string());
+            save_setting("lscat", "image_x_spacing", &spacing.to_string());
+        }
+    }
+    system().clear();
+    creds
+}
+
+/// Logs in on pixiv's site and saves the refresh token
+pub fn ask_login() -> Credentials {
+    let tokens = loop {
+        match auth::login_interactively() {
+            Some(tokens) => break tokens,
+            None => println!("Failed to log in! Please try again\n"),
+        }
+    };
+    let passphrase = ask_encrypt_refresh_token(&tokens.refresh_token);
+    let with_refresh_token = passphrase.is_none();
     let creds = Credentials {
-        username,
-        password,
-        your_id,
+        refresh_token: tokens.refresh_token,
+        your_id: tokens.user_id,
+        passphrase,
     };
-    let samecreds = write_config(creds);
-    append_default_config();
-    samecreds
+    write_config(creds, with_refresh_token)
 }
 
-fn ask_credentials() -> (String, String) {
-    println!("Please enter your username:");
-    let mut username = String::new();
-    io::stdin().read_line(&mut username).unwrap();
-
-    // TODO: hide password entry
-    println!("Please enter your password:");
-    let mut password = String::new();
-    io::stdin().read_line(&mut password).unwrap();
-
-    (username, password)
+/// Saves a refresh token that pixiv rotated, in the config or encrypted
+/// with `passphrase`, wherever the old one was
+pub fn save_refresh_token(refresh_token: &str, passphrase: Option<&str>) -> Option<()> {
+    match passphrase {
+        Some(passphrase) => secret::write_secret(refresh_token, passphrase),
+        None => {
+            save_setting("Credentials", "refresh_token", refresh_token);
+            Some(())
+        }
+    }
 }
 
-fn ask_your_id() -> String {
-    println!("Do you want to save your pixiv ID? It will be more convenient");
-    println!("to view artists you are following");
+/// `auto` unless the user picks one, so that the config works in other terminals
+fn ask_renderer() -> String {
+    let detected = Backend::from_env();
+    println!(
+        "\nYour terminal seems to support {} graphics, which will be used to show images.",
+        detected.as_str()
+    );
+    println!("Press enter to keep detecting this, or enter kitty, sixel, iterm2 or blocks");
+    let ans = prompt::input("to always use it: ");
+    match Backend::from_name(&ans.to_lowercase()) {
+        Some(backend) => backend.as_str().to_string(),
+        None => "auto".to_string(),
+    }
+}
 
+/// Returns the passphrase if the refresh token was saved encrypted, in which
+/// case it is left out of the config
+fn ask_encrypt_refresh_token(refresh_token: &str) -> Option<String> {
+    println!("Your login will be saved in the config, which only you can read.");
+    println!("Do you want to encrypt it with a passphrase instead? [y/N]");
     let mut ans = String::new();
     io::stdin().read_line(&mut ans).unwrap();
-    match ans.as_str() {
-        "y" | "" => {
-            println!("Please enter your pixiv ID:");
-            let mut your_id = String::new();
-            io::stdin().read_line(&mut your_id).unwrap();
-            your_id
+    if !matches!(ans.trim(), "y" | "Y") {
+        return None;
+    }
+    loop {
+        let passphrase = prompt::hidden_input("Enter a passphrase: ");
+        if passphrase.is_empty() {
+            println!("The passphrase cannot be empty!");
+        } else if prompt::hidden_input("Enter it again: ") != passphrase {
+            println!("The passphrases do not match!");
+        } else if secret::write_secret(refresh_token, &passphrase).is_some() {
+            return Some(passphrase);
+        } else {
+            println!("Failed to save the encrypted login, saving it in the config instead");
+            return None;
         }
-        _ => "".to_string(),
     }
 }
 
-fn write_config(creds: Credentials) -> Credentials {
-    Command::new("clear").spawn();
-    fs::create_dir_all(Path::new(CONFIGPATH).parent().unwrap());
-    let mut buffer = File::create("config.ini").unwrap();
-    write!(
-        buffer,
-        "username={}\npassword={}",
-        creds.username, creds.password
-    );
-    if creds.your_id != "" {
-        write!(buffer, "\nID={}", creds.your_id).unwrap()
+fn credentials_section(creds: &Credentials, with_refresh_token: bool) -> String {
+    let mut section = String::from("[Credentials]\n");
+    if with_refresh_token {
+        section.push_str(&format!("refresh_token = {}\n", creds.refresh_token));
+    }
+    if !creds.your_id.is_empty() {
+        section.push_str(&format!("ID = {}\n", creds.your_id));
+    }
+    section
+}
+
+/// Replaces a section, header included, with `section`; or puts
+/// `section` at the top if there was no such section
+fn replace_section(raw: &str, section_name: &str, section: &str) -> String {
+    let section_head = format!("[{}]", section_name);
+    let lines: Vec<&str> = raw.lines().collect();
+    let start = match lines
+        .iter()
+        .position(|line| line.trim().eq_ignore_ascii_case(&section_head))
+    {
+        Some(start) => start,
+        None if raw.is_empty() => return section.to_string(),
+        None => return format!("{}\n{}", section, raw),
+    };
+    let end = lines[start + 1..]
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .map_or(lines.len(), |idx| start + 1 + idx);
+    let mut result: Vec<String> = lines[..start].iter().map(|line| line.to_string()).collect();
+    result.push(section.to_string());
+    if end < lines.len() {
+        result.push(lines[end..].join("\n") + "\n");
     }
+    result.join("\n")
+}
+
+/// Only the credentials are replaced, so a config from before
+/// pixiv stopped accepting passwords keeps its other settings
+fn write_config(creds: Credentials, with_refresh_token: bool) -> Credentials {
+    system().clear();
+    let raw = replace_section(
+        &read_raw().unwrap_or_default(),
+        "Credentials",
+        &credentials_section(&creds, with_refresh_token),
+    );
+    files::write_private(&pure::expand_home(Path::new(CONFIGPATH)), raw.as_bytes()).unwrap();
     creds
 }
 
-fn append_default_config() {
-    let example_cfg = Path::new("~/.local/share/koneko/example_config.ini");
-    // FIXME
-    //Command::new("tail").args([example_cfg, "-n", "+9"]).stdout(CONFIGPATH);
+/// A section with every setting in `SETTINGS` at its default, with its comment above it
+fn default_section(section_name: &str) -> String {
+    let settings: Vec<String> = SETTINGS
+        .iter()
+        .filter(|setting| setting.section == section_name)
+        .map(|setting| {
+            let comment: String = setting
+                .comment
+                .lines()
+                .map(|line| format!("# {}\n", line))
+                .collect();
+            format!("{}{} = {}\n", comment, setting.key, setting.default)
+        })
+        .collect();
+    format!("[{}]\n{}", section_name, settings.join("\n"))
+}
+
+fn default_section_names() -> Vec<&'static str> {
+    let mut names: Vec<&str> = vec![];
+    for setting in SETTINGS {
+        if !names.contains(&setting.section) {
+            names.push(setting.section);
+        }
+    }
+    names
+}
+
+/// Adds the sections in `SETTINGS` that are not in the config yet
+fn add_default_sections(raw: &str) -> String {
+    let mut result = raw.to_string();
+    for name in default_section_names() {
+        if section_in_raw(raw, name).is_none() {
+            if !result.is_empty() {
+                result.push('\n');
+            }
+            result.push_str(&default_section(name));
+        }
+    }
+    result
+}
+
+/// Sets a setting in place, keeping everything else in the config as it is
+fn set_in_raw(raw: &str, section_name: &str, key: &str, value: &str) -> String {
+    let section_head = format!("[{}]", section_name);
+    let new_line = format!("{} = {}", key, value);
+    let mut lines: Vec<String> = raw.lines().map(|line| line.to_string()).collect();
+    let start = match lines
+        .iter()
+        .position(|line| line.trim().eq_ignore_ascii_case(&section_head))
+    {
+        Some(start) => start,
+        None => {
+            let section = format!("{}\n{}\n", section_head, new_line);
+            return add_section(raw, &section);
+        }
+    };
+    let end = lines[start + 1..]
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .map_or(lines.len(), |idx| start + 1 + idx);
+    let is_key = |line: &String| {
+        line.split('=')
+            .next()
+            .is_some_and(|name| line.contains('=') && name.trim().eq_ignore_ascii_case(key))
+    };
+    match lines[start + 1..end].iter().position(is_key) {
+        Some(idx) => lines[start + 1 + idx] = new_line,
+        None => {
+            // After the last setting, rather than after the blank lines before the next section
+            let last = lines[start..end]
+                .iter()
+                .rposition(|line| !line.trim().is_empty())
+                .unwrap();
+            lines.insert(start + last + 1, new_line);
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+fn add_section(raw: &str, section: &str) -> String {
+    if raw.trim().is_empty() {
+        section.to_string()
+    } else {
+        format!("{}\n\n{}", raw.trim_end(), section)
+    }
+}
+
+fn update_config(update: impl FnOnce(&str) -> String) {
+    let raw = update(&read_raw().unwrap_or_default());
+    files::write_private(&pure::expand_home(Path::new(CONFIGPATH)), raw.as_bytes()).unwrap();
+}
+
+pub fn save_setting(section_name: &str, key: &str, value: &str) {
+    update_config(|raw| set_in_raw(raw, section_name, key, value));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RAW: &str = "[Credentials]\nusername = koneko\nPassword = HunTer2\nID = 2232374\n\n[lscat]\nimage_width = 20\nimages_x_spacing=3\n";
+
+    #[test]
+    fn test_credentials_section() {
+        let mut creds = Credentials {
+            refresh_token: "refresh".to_string(),
+            your_id: "2232374".to_string(),
+            passphrase: None,
+        };
+        let section = credentials_section(&creds, true);
+        assert_eq!(
+            section,
+            "[Credentials]\nrefresh_token = refresh\nID = 2232374\n"
+        );
+        let section = section_in_raw(&section, "Credentials").unwrap();
+        assert_eq!(
+            setting_in_section(&section, "refresh_token").unwrap(),
+            "refresh"
+        );
+
+        creds.your_id = String::new();
+        assert_eq!(credentials_section(&creds, false), "[Credentials]\n");
+    }
+
+    #[test]
+    fn test_replace_section() {
+        let section = "[Credentials]\nrefresh_token = refresh\n";
+        assert_eq!(
+            replace_section(RAW, "credentials", section),
+            "[Credentials]\nrefresh_token = refresh\n\n[lscat]\nimage_width = 20\nimages_x_spacing=3\n"
+        );
+        assert_eq!(
+            replace_section("[lscat]\nimage_width = 20\n", "Credentials", section),
+            "[Credentials]\nrefresh_token = refresh\n\n[lscat]\nimage_width = 20\n"
+        );
+        assert_eq!(replace_section("", "Credentials", section), section);
+        assert_eq!(
+            replace_section(
+                "[misc]\ntext_only = true\n[Credentials]\nID = 1\n",
+                "Credentials",
+                section
+            ),
+            "[misc]\ntext_only = true\n[Credentials]\nrefresh_token = refresh\n"
+        );
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_take_overrides() {
+        let (overrides, rest) = take_overrides(args(&[
+            "--set",
+            "lscat.image_width=20",
+            "1",
+            "--set=misc.print_info = false",
+            "https://www.pixiv.net/en/users/2232374",
+        ]))
+        .unwrap();
+        assert_eq!(
+            overrides,
+            vec![
+                Override {
+                    section: "lscat".to_string(),
+                    key: "image_width".to_string(),
+                    value: "20".to_string(),
+                },
+                Override {
+                    section: "misc".to_string(),
+                    key: "print_info".to_string(),
+                    value: "false".to_string(),
+                },
+            ]
+        );
+        assert_eq!(rest, args(&["1", "https://www.pixiv.net/en/users/2232374"]));
+
+        assert!(take_overrides(args(&["--set"])).is_err());
+        assert!(take_overrides(args(&["--set", "image_width=20"])).is_err());
+        assert!(take_overrides(args(&["--set", "lscat.image_width"])).is_err());
+        assert_eq!(parse_override(".image_width=20"), None);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let overrides = vec![
+            parse_override("lscat.image_width=19").unwrap(),
+            parse_override("LSCAT.IMAGE_WIDTH=21").unwrap(),
+        ];
+        let env = |name: &str| match name {
+            "KONEKO_LSCAT_IMAGE_WIDTH" | "KONEKO_LSCAT_IMAGES_X_SPACING" => Some("5".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            resolve("lscat", "image_width", &overrides, env, Some(RAW)),
+            Some(("21".to_string(), Source::Flag))
+        );
+        assert_eq!(
+            resolve("lscat", "images_x_spacing", &overrides, env, Some(RAW)),
+            Some(("5".to_string(), Source::Env))
+        );
+        assert_eq!(
+            resolve("Credentials", "ID", &overrides, env, Some(RAW)),
+            Some(("2232374".to_string(), Source::File))
+        );
+        assert_eq!(
+            resolve("lscat", "page_spacing", &overrides, env, Some(RAW)),
+            None
+        );
+        assert_eq!(
+            resolve("lscat", "images_x_spacing", &[], |_| None, None),
+            None
+        );
+        assert_eq!(
+            Source::Env.describe("misc", "print_info"),
+            "KONEKO_MISC_PRINT_INFO"
+        );
+    }
+
+    #[test]
+    fn test_invalid_value() {
+        let find = |key: &str| SETTINGS.iter().find(|setting| setting.key == key).unwrap();
+        assert_eq!(invalid_value(find("image_width"), "20"), None);
+        assert_eq!(
+            invalid_value(find("image_width"), "wide"),
+            Some("lscat.image_width = wide should be a number; using 18".to_string())
+        );
+        assert_eq!(invalid_value(find("print_info"), "False"), None);
+        assert!(invalid_value(find("print_info"), "no").is_some());
+        assert_eq!(
+            invalid_value(find("gallery_print_spacing"), "9, 17,17"),
+            None
+        );
+        assert!(invalid_value(find("gallery_print_spacing"), "9;17").is_some());
+        assert_eq!(invalid_value(find("renderer"), "Kitty"), None);
+        assert!(invalid_value(find("renderer"), "w3m").is_some());
+        assert_eq!(invalid_value(find("max_x_restrict"), "0"), None);
+        assert_eq!(
+            invalid_value(find("max_x_restrict"), "3"),
+            Some("filter.max_x_restrict = 3 should be 0, 1 or 2; using 2".to_string())
+        );
+        assert!(invalid_value(find("max_x_restrict"), "r18").is_some());
+        assert_eq!(
+            invalid_value(find("blocked_artists"), "2232374, 219621"),
+            None
+        );
+        assert!(invalid_value(find("blocked_artists"), "raika9").is_some());
+        assert_eq!(
+            invalid_value(find("download_template"), "~/Downloads/{Title}.{ext}"),
+            Some(
+                "misc.download_template = ~/Downloads/{Title}.{ext} should be a path with only \
+                 the placeholders {artist_name} {artist_id} {date} {title} {id} {page} {ext}; \
+                 using ~/Downloads/{id}_p{page}.{ext}"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            invalid_value(find("download_template"), "~/Pixiv/{artist_name}/{id}.{ext}"),
+            None
+        );
+        for setting in SETTINGS {
+            assert_eq!(invalid_value(setting, setting.default), None);
+        }
+    }
+
+    #[test]
+    fn test_default_sections() {
+        let raw = add_default_sections("");
+        assert!(raw.starts_with("[lscat]\n# "));
+        for setting in SETTINGS {
+            let section = section_in_raw(&raw, setting.section).unwrap();
+            assert_eq!(
+                setting_in_section(&section, setting.key).unwrap(),
+                setting.default
+            );
+        }
+        let raw = add_default_sections(RAW);
+        assert!(raw.starts_with(RAW));
+        let section = section_in_raw(&raw, "lscat").unwrap();
+        assert_eq!(setting_in_section(&section, "image_width").unwrap(), "20");
+        assert!(section_in_raw(&raw, "experimental").is_some());
+    }
+
+    #[test]
+    fn test_set_in_raw() {
+        assert_eq!(
+            set_in_raw(RAW, "lscat", "IMAGE_WIDTH", "18"),
+            RAW.replace("image_width = 20", "IMAGE_WIDTH = 18")
+        );
+        assert_eq!(
+            set_in_raw(RAW, "Credentials", "renderer", "kitty"),
+            RAW.replace("2232374\n", "2232374\nrenderer = kitty\n")
+        );
+        assert_eq!(
+            set_in_raw(RAW, "misc", "text_only", "true"),
+            format!("{}\n[misc]\ntext_only = true\n", RAW)
+        );
+        assert_eq!(
+            set_in_raw("", "misc", "text_only", "true"),
+            "[misc]\ntext_only = true\n"
+        );
+    }
+
+    #[test]
+    fn test_section_in_raw() {
+        assert_eq!(
+            section_in_raw(RAW, "credentials").unwrap(),
+            "username = koneko\nPassword = HunTer2\nID = 2232374\n"
+        );
+        assert_eq!(
+            section_in_raw(RAW, "lscat").unwrap(),
+            "image_width = 20\nimages_x_spacing=3"
+        );
+        assert_eq!(section_in_raw(RAW, "misc"), None);
+    }
+
+    #[test]
+    fn test_setting_in_section() {
+        let section = section_in_raw(RAW, "Credentials").unwrap();
+        assert_eq!(setting_in_section(&section, "password").unwrap(), "HunTer2");
+        assert_eq!(setting_in_section(&section, "id").unwrap(), "2232374");
+        assert_eq!(setting_in_section(&section, "user"), None);
+
+        let section = section_in_raw(RAW, "lscat").unwrap();
+        assert_eq!(setting_in_section(&section, "images_x_spacing").unwrap(), "3");
+    }
 }
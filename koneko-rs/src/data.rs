@@ -5,7 +5,10 @@ use std::path::{Path, PathBuf};
 use serde_json::*;
 
 use crate::pure;
+use crate::config;
 use crate::KONEKODIR;
+use crate::api::Restrict;
+use crate::filter::Filter;
 
 pub enum DataStruct {
     Gallery,
@@ -13,23 +16,63 @@ pub enum DataStruct {
     UserData,
 }
 
+pub enum GalleryMode {
+    Artist,
+    TagSearch,
+    Ranking,
+    Recommended,
+    Related,
+    Bookmarks {
+        user_id: String,
+        restrict: Restrict,
+        tag: Option<String>,
+    },
+}
+
+#[derive(Clone)]
+pub enum UserMode {
+    Following { user_id: String, restrict: Restrict },
+}
+
+pub fn bookmarks_path(restrict: Restrict, tag: Option<&str>) -> PathBuf {
+    let path = Path::new(KONEKODIR).join("bookmarks").join(restrict.as_str());
+    match tag {
+        Some(tag) => path.join(tag.replace('/', "")),
+        None => path,
+    }
+}
+
+pub fn following_path(user_id: &str, restrict: Restrict) -> PathBuf {
+    Path::new(KONEKODIR)
+        .join("following")
+        .join(user_id)
+        .join(restrict.as_str())
+}
+
 pub struct Gallery {
     pub page_num: i32,
     pub main_path: PathBuf,
     pub offset: i32,
     pub all_pages_cache: HashMap<i32, Value>,
+    pub mode: GalleryMode,
+    pub filter: Filter,
+    /// Where the posts hidden by the filter were in each page
+    pub hidden_cache: HashMap<i32, Vec<usize>>,
 }
 
 pub struct User {
     pub page_num: i32,
     pub main_path: PathBuf,
     pub offset: i32,
+    pub mode: UserMode,
+    pub filter: Filter,
 }
 
 pub struct UserData {
     pub page_num: i32,
     pub main_path: PathBuf,
     pub offset: i32,
+    pub mode: UserMode,
     pub next_url: String,
     pub ids_cache: HashMap<i32, Vec<String>>,
     pub names_cache: HashMap<i32, Vec<String>>,
@@ -39,9 +82,14 @@ pub struct UserData {
     // plus lifetime mismatches at `impl Data for UserData`
     pub image_urls: Vec<String>,
     pub splitpoint: i32,
+    pub all_pages_cache: HashMap<i32, Value>,
+    pub filter: Filter,
+    /// How many users and posts in the current page are hidden by the filter
+    pub hidden: usize,
 }
 
 pub struct Image<'a> {
+    pub raw: &'a Value,
     pub image_id: &'a str,
     pub artist_user_id: String,
     pub page_num: i32,
@@ -49,6 +97,8 @@ pub struct Image<'a> {
     pub number_of_pages: i32,
     pub download_path: PathBuf,
     pub firstmode: bool,
+    pub is_bookmarked: bool,
+    pub is_followed: bool,
 }
 
 pub trait Data {
@@ -74,7 +124,7 @@ pub trait Data {
 
 impl Data for Gallery {
     fn update(&mut self, raw: &Value) {
-        self.all_pages_cache.insert(self.page_num, raw.clone());
+        self.insert_page(self.page_num, raw);
     }
 
     fn download_path(&self) -> PathBuf {
@@ -88,8 +138,8 @@ impl Data for Gallery {
     fn next_url(&self) -> Option<String> {
         Some(
             self.all_pages_cache.get(&self.page_num)?["next_url"]
-                .to_string()
-                .replace("\"", ""),
+                .as_str()?
+                .to_string(),
         )
     }
 
@@ -103,6 +153,40 @@ impl Data for Gallery {
 }
 
 impl Gallery {
+    pub fn new(main_path: PathBuf, mode: GalleryMode) -> Gallery {
+        Gallery {
+            page_num: 1,
+            main_path,
+            offset: 0,
+            all_pages_cache: HashMap::new(),
+            mode,
+            filter: config::filter_config(),
+            hidden_cache: HashMap::new(),
+        }
+    }
+
+    /// Caches a page, without the posts hidden by the filter
+    pub fn insert_page(&mut self, page_num: i32, raw: &Value) {
+        let mut raw = raw.clone();
+        let hidden = self.filter.filter_illusts(&mut raw["illusts"]);
+        self.hidden_cache.insert(page_num, hidden);
+        self.all_pages_cache.insert(page_num, raw);
+    }
+
+    /// Moves to another directory, such as the other restrict of the bookmarks,
+    /// starting again from page 1 without anything cached for the old one
+    pub fn reset(&mut self, main_path: PathBuf) {
+        self.page_num = 1;
+        self.main_path = main_path;
+        self.offset = 0;
+        self.all_pages_cache.clear();
+        self.hidden_cache.clear();
+    }
+
+    pub fn hidden_count(&self) -> usize {
+        self.hidden_cache.get(&self.page_num).map_or(0, |x| x.len())
+    }
+
     pub fn current_illusts(&self) -> Option<&Value> {
         Some(&self.all_pages_cache.get(&self.page_num)?["illusts"])
     }
@@ -118,6 +202,44 @@ impl Gallery {
     pub fn url(&self, number: i32) -> String {
         pure::url_given_size(self.post_json(number), "large")
     }
+
+    pub fn is_bookmarked(&self, post_number: i32) -> bool {
+        self.post_json(post_number)["is_bookmarked"]
+            .as_bool()
+            .unwrap_or(false)
+    }
+
+    /// Keeps the cached page in sync after bookmarking or unbookmarking a post
+    pub fn set_bookmarked(&mut self, post_number: i32, is_bookmarked: bool) {
+        if let Some(page) = self.all_pages_cache.get_mut(&self.page_num) {
+            page["illusts"][post_number as usize]["is_bookmarked"] = Value::Bool(is_bookmarked);
+        }
+    }
+
+    /// Labels to show above each thumbnail, if the mode has any
+    pub fn labels(&self) -> Vec<String> {
+        match self.mode {
+            GalleryMode::Ranking => self.rank_labels(),
+            _ => vec![],
+        }
+    }
+
+    /// Hidden posts still count towards the ranks of the posts after them
+    fn rank_labels(&self) -> Vec<String> {
+        let page_len = |page_num: &i32| match self.all_pages_cache.get(page_num) {
+            Some(raw) => {
+                raw["illusts"].as_array().map_or(0, |x| x.len())
+                    + self.hidden_cache.get(page_num).map_or(0, |x| x.len())
+            }
+            None => 30,
+        };
+        let previous_posts: usize = (1..self.page_num).map(|x| page_len(&x)).sum();
+        let hidden = self.hidden_cache.get(&self.page_num).cloned().unwrap_or_default();
+        (0..page_len(&self.page_num))
+            .filter(|idx| !hidden.contains(idx))
+            .map(|idx| format!("#{}", previous_posts + idx + 1))
+            .collect()
+    }
 }
 
 impl Data for UserData {
@@ -126,17 +248,21 @@ impl Data for UserData {
             page_num: self.page_num,
             main_path: self.main_path.clone(),
             offset: self.offset,
+            mode: self.mode.clone(),
+            filter: self.filter.clone(),
         };
         let newuserdata = user.update(raw);
         self.page_num = newuserdata.page_num;
         self.main_path = newuserdata.main_path;
         self.offset = newuserdata.offset;
         self.next_url = newuserdata.next_url;
-        self.ids_cache = newuserdata.ids_cache;
-        self.names_cache = newuserdata.names_cache;
+        self.ids_cache.extend(newuserdata.ids_cache);
+        self.names_cache.extend(newuserdata.names_cache);
         self.profile_pic_urls = newuserdata.profile_pic_urls;
         self.image_urls = newuserdata.image_urls;
         self.splitpoint = newuserdata.splitpoint;
+        self.all_pages_cache.extend(newuserdata.all_pages_cache);
+        self.hidden = newuserdata.hidden;
     }
 
     fn download_path(&self) -> PathBuf {
@@ -148,7 +274,11 @@ impl Data for UserData {
     }
 
     fn next_url(&self) -> Option<String> {
-        Some(self.next_url.clone())
+        if self.next_url == "null" {
+            None
+        } else {
+            Some(self.next_url.clone())
+        }
     }
 
     fn all_urls(&self) -> Vec<String> {
@@ -206,10 +336,17 @@ impl Mappable for Value {
 
 impl User {
     pub fn update(&self, raw: &Value) -> UserData {
+        let mut raw = raw.clone();
+        let hidden = self.filter.filter_user_previews(&mut raw["user_previews"]);
+        let raw = &raw;
+
         let next_url = raw["next_url"].to_string().replace("\"", "");
         let page = &raw["user_previews"];
 
         let ids = page.map_string(|x| &x["user"]["id"]);
+        let mut all_pages_cache = HashMap::new();
+        all_pages_cache.insert(self.page_num, raw.clone());
+
         let mut ids_cache = HashMap::new();
         ids_cache.insert(self.page_num, ids);
 
@@ -233,7 +370,7 @@ impl User {
                 if url.is_null() {
                     break;
                 }
-                image_urls.push(url.to_string());
+                image_urls.push(url.to_string().replace("\"", ""));
                 j += 1;
             }
             i += 1;
@@ -243,12 +380,16 @@ impl User {
             page_num: self.page_num,
             main_path: self.main_path.clone(),
             offset: self.offset,
+            mode: self.mode.clone(),
             next_url,
             ids_cache,
             names_cache,
             profile_pic_urls,
             image_urls,
             splitpoint,
+            all_pages_cache,
+            filter: self.filter.clone(),
+            hidden,
         }
     }
 }
@@ -257,6 +398,23 @@ impl UserData {
     pub fn names(&self) -> &Vec<String> {
         self.names_cache.get(&self.page_num).unwrap()
     }
+
+    /// The user and their preview illusts, as given by the api
+    pub fn user_preview(&self, selected_user_num: i32) -> &Value {
+        &self.all_pages_cache.get(&self.page_num).unwrap()["user_previews"]
+            [selected_user_num as usize]
+    }
+
+    /// How many preview illusts each user in the current page has
+    pub fn preview_counts(&self) -> Vec<usize> {
+        match self.all_pages_cache.get(&self.page_num).unwrap()["user_previews"].as_array() {
+            Some(previews) => previews
+                .iter()
+                .map(|x| x["illusts"].as_array().map_or(0, |illusts| illusts.len()))
+                .collect(),
+            None => vec![],
+        }
+    }
 }
 
 
@@ -272,16 +430,39 @@ impl Image<'_> {
             download_path = download_path.join(image_id);
         }
         Image {
+            raw,
             image_id,
             artist_user_id,
             page_num: 0,
             page_urls,
             number_of_pages,
             download_path,
-            firstmode
+            firstmode,
+            is_bookmarked: raw["is_bookmarked"].as_bool().unwrap_or(false),
+            is_followed: raw["user"]["is_followed"].as_bool().unwrap_or(false),
         }
     }
 
+    /// The artist in the same shape as an entry of a following page,
+    /// with this post as the only preview
+    pub fn artist_preview(&self) -> Value {
+        json!({"user": self.raw["user"], "illusts": [self.raw]})
+    }
+
+    pub fn info_line(&self) -> String {
+        let bookmarked = if self.is_bookmarked {
+            "Bookmarked"
+        } else {
+            "Not bookmarked"
+        };
+        format!(
+            "Page {}/{} | {}",
+            self.page_num + 1,
+            self.number_of_pages,
+            bookmarked
+        )
+    }
+
     pub fn current_url(&self) -> &str {
         &self.page_urls[self.page_num as usize]
     }
@@ -298,6 +479,24 @@ impl Image<'_> {
         self.download_path.join(self.image_filename())
     }
 
+    /// The values of `pure::TEMPLATE_FIELDS` for downloading `url`, page `page_num` of this post
+    pub fn template_fields(&self, page_num: i32, url: &str) -> Vec<(&'static str, String)> {
+        let filename = pure::split_backslash_last(url);
+        let ext = filename.rsplit('.').next().unwrap_or("");
+        let text = |value: &Value| value.as_str().unwrap_or("").to_string();
+        // Only the day, from a timestamp like 2019-09-09T04:32:38+09:00
+        let date = text(&self.raw["create_date"]);
+        vec![
+            ("artist_name", text(&self.raw["user"]["name"])),
+            ("artist_id", self.artist_user_id.clone()),
+            ("date", date.get(..10).unwrap_or(&date).to_string()),
+            ("title", text(&self.raw["title"])),
+            ("id", self.image_id.to_string()),
+            ("page", page_num.to_string()),
+            ("ext", ext.to_string()),
+        ]
+    }
+
     pub fn large_filename(&self) -> &str {
         pure::split_backslash_last(&self.page_urls[0])
     }
@@ -339,6 +538,9 @@ mod tests {
             main_path: Path::new(KONEKODIR).join("2232374"),
             offset: 0,
             all_pages_cache: HashMap::new(),
+            mode: GalleryMode::Artist,
+            filter: Filter::default(),
+            hidden_cache: HashMap::new(),
         }
     }
 
@@ -355,6 +557,11 @@ mod tests {
             page_num: 1,
             main_path: Path::new(KONEKODIR).join("following/2232374"),
             offset: 0,
+            mode: UserMode::Following {
+                user_id: "2232374".to_string(),
+                restrict: Restrict::Private,
+            },
+            filter: Filter::default(),
         }
     }
 
@@ -559,6 +766,92 @@ mod tests {
         )
     }
 
+    #[rstest]
+    fn test_gallery_set_bookmarked(gallery_json: Value) {
+        let mut gdata = gallery_updated(gallery_json);
+        assert!(!gdata.is_bookmarked(3));
+        gdata.set_bookmarked(3, true);
+        assert!(gdata.is_bookmarked(3));
+        assert!(!gdata.is_bookmarked(4));
+        gdata.set_bookmarked(3, false);
+        assert!(!gdata.is_bookmarked(3));
+    }
+
+    #[rstest]
+    fn test_gallery_labels(gallery_json: Value) {
+        let mut gdata = gallery_updated(gallery_json.clone());
+        assert!(gdata.labels().is_empty());
+
+        gdata.mode = GalleryMode::Ranking;
+        assert_eq!(gdata.labels()[..3], ["#1", "#2", "#3"]);
+
+        gdata.page_num = 2;
+        gdata.update(&gallery_json);
+        assert_eq!(gdata.labels().len(), 30);
+        assert_eq!(gdata.labels()[0], "#31");
+        assert_eq!(gdata.labels()[29], "#60");
+    }
+
+    #[rstest]
+    fn test_gallery_filter(gallery_json: Value) {
+        let mut gdata = gallery();
+        gdata.mode = GalleryMode::Ranking;
+        gdata.filter.blocked_artists = vec!["2232374".to_string()];
+        gdata.update(&gallery_json);
+        assert_eq!(gdata.hidden_count(), 30);
+        assert!(gdata.all_urls().is_empty());
+
+        let mut raw = gallery_json.clone();
+        raw["illusts"][1]["x_restrict"] = Value::from(1);
+        gdata.filter = Filter {
+            max_x_restrict: 0,
+            ..Filter::default()
+        };
+        gdata.update(&raw);
+        assert_eq!(gdata.hidden_count(), 1);
+        assert_eq!(gdata.all_urls().len(), 29);
+        assert_eq!(gdata.labels()[..3], ["#1", "#3", "#4"]);
+    }
+
+    #[rstest]
+    fn test_gallery_reset(gallery_json: Value) {
+        let mut gdata = gallery();
+        gdata.filter.blocked_artists = vec!["2232374".to_string()];
+        gdata.update(&gallery_json);
+        gdata.page_num = 2;
+        gdata.update(&gallery_json);
+
+        gdata.reset(bookmarks_path(Restrict::Private, None));
+        assert_eq!(gdata.page_num, 1);
+        assert_eq!(gdata.main_path, bookmarks_path(Restrict::Private, None));
+        assert!(gdata.all_pages_cache.is_empty());
+        assert_eq!(gdata.hidden_count(), 0);
+        gdata.page_num = 2;
+        assert_eq!(gdata.hidden_count(), 0);
+    }
+
+    #[rstest]
+    fn test_user_filter(user_json: Value) {
+        let mut data = user();
+        data.filter.blocked_artists = vec!["219621".to_string()];
+        let udata = data.update(&user_json);
+        assert_eq!(udata.hidden, 1);
+        assert_eq!(udata.names().len(), 29);
+        assert_eq!(udata.profile_pic_urls.len(), 29);
+    }
+
+    #[rstest]
+    fn test_bookmarks_path() {
+        assert_eq!(
+            bookmarks_path(Restrict::Public, None),
+            Path::new(KONEKODIR).join("bookmarks/public")
+        );
+        assert_eq!(
+            bookmarks_path(Restrict::Private, Some("a/b")),
+            Path::new(KONEKODIR).join("bookmarks/private/ab")
+        );
+    }
+
     #[rstest]
     fn test_user_init() {
         let udata = user();
@@ -607,6 +900,23 @@ mod tests {
         assert_eq!(udata.artist_user_id(0), "219621");
     }
 
+    #[rstest]
+    fn test_user_preview(user_json: Value) {
+        let udata = user_updated(user_json);
+        assert_eq!(udata.user_preview(1)["user"]["name"], "ざるつ");
+        let counts = udata.preview_counts();
+        assert_eq!(counts.len(), 30);
+        assert_eq!(counts.iter().sum::<usize>(), udata.image_urls.len());
+    }
+
+    #[test]
+    fn test_following_path() {
+        assert_eq!(
+            following_path("2232374", Restrict::Public),
+            Path::new(KONEKODIR).join("following/2232374/public")
+        );
+    }
+
     #[rstest]
     fn test_user_names(user_json: Value) {
         let udata = user_updated(user_json);
@@ -724,6 +1034,24 @@ mod tests {
         );
     }
 
+    #[rstest]
+    fn test_image_info_line(image_json: Value) {
+        let mut idata = Image::new(&image_json, "76695217", false);
+        assert!(!idata.is_bookmarked);
+        assert_eq!(idata.info_line(), "Page 1/8 | Not bookmarked");
+        idata.page_num = 2;
+        idata.is_bookmarked = true;
+        assert_eq!(idata.info_line(), "Page 3/8 | Bookmarked");
+    }
+
+    #[rstest]
+    fn test_image_artist_preview(image_json: Value) {
+        let idata = Image::new(&image_json, "76695217", false);
+        let preview = idata.artist_preview();
+        assert_eq!(preview["user"]["id"].to_string(), idata.artist_user_id);
+        assert_eq!(preview["illusts"][0]["id"], 76695217);
+    }
+
     #[rstest]
     fn test_image_current_url(image_json: Value) {
         let idata = Image::new(&image_json, "76695217", false);
@@ -732,4 +1060,22 @@ mod tests {
             "https://i.pximg.net/c/600x1200_90_webp/img-master/img/2019/09/09/04/32/38/76695217_p0_master1200.jpg"
         );
     }
+
+    #[rstest]
+    fn test_image_template_fields(image_json: Value) {
+        let image = Image::new(&image_json, "76695217", false);
+        let url = "https://i.pximg.net/img-original/img/2019/09/09/04/32/38/76695217_p2.png";
+        assert_eq!(
+            image.template_fields(2, url),
+            vec![
+                ("artist_name", "raika9".to_string()),
+                ("artist_id", "2232374".to_string()),
+                ("date", "2019-09-09".to_string()),
+                ("title", "ツイッターまとめ".to_string()),
+                ("id", "76695217".to_string()),
+                ("page", "2".to_string()),
+                ("ext", "png".to_string()),
+            ]
+        );
+    }
 }
@@ -0,0 +1,500 @@
+//! Finding the same art under different posts, such as reposts and re-uploads.
+//! Downloads and the images in the cache are indexed by a perceptual hash,
+//! which stays close when an image is resized or re-encoded
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::UNIX_EPOCH;
+
+use image::DynamicImage;
+use image::imageops::FilterType;
+use serde_json::{json, Value};
+
+use crate::config;
+use crate::pure;
+use crate::renderer;
+use crate::KONEKODIR;
+
+const INDEX_PATH: &str = "~/.local/share/koneko/hashes.json";
+
+/// Images whose hashes differ in at most this many of the 64 bits are near-duplicates
+const MAX_DISTANCE: u32 = 6;
+
+const IMAGE_EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "png", "webp", "gif"];
+
+/// A difference hash: each bit is whether a pixel is brighter than the one to its
+/// right, in a 9x8 grayscale copy of the image
+pub fn dhash(image: &DynamicImage) -> u64 {
+    let small = image
+        .grayscale()
+        .resize_exact(9, 8, FilterType::Triangle)
+        .to_luma8();
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | brighter as u64;
+        }
+    }
+    hash
+}
+
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Every image under a directory, skipping hidden files and directories such
+/// as thumbnails and unfinished downloads
+fn image_files(dir: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut files = vec![];
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let hidden = entry
+            .file_name()
+            .to_str()
+            .is_none_or(|name| name.starts_with('.'));
+        match entry.file_type() {
+            _ if hidden => {}
+            Ok(kind) if kind.is_dir() => files.extend(image_files(&path)),
+            Ok(kind) if kind.is_file() && is_image(&path) => files.push(path),
+            _ => {}
+        }
+    }
+    files
+}
+
+/// The modification time in seconds and the size, to tell when a file was replaced
+fn file_stamp(path: &Path) -> Option<(u64, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((modified.as_secs(), meta.len()))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Entry {
+    modified: u64,
+    len: u64,
+    hash: u64,
+}
+
+/// The hash of every indexed image, kept on disk so that only new or
+/// changed files are decoded again
+#[derive(Debug, Default, PartialEq)]
+pub struct Index {
+    entries: BTreeMap<PathBuf, Entry>,
+}
+
+impl Index {
+    fn from_json(raw: &Value) -> Index {
+        let entries = raw
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(path, entry)| {
+                let entry = Entry {
+                    modified: entry[0].as_u64()?,
+                    len: entry[1].as_u64()?,
+                    hash: u64::from_str_radix(entry[2].as_str()?, 16).ok()?,
+                };
+                Some((PathBuf::from(path), entry))
+            })
+            .collect();
+        Index { entries }
+    }
+
+    fn to_json(&self) -> Value {
+        let entries = self
+            .entries
+            .iter()
+            .filter_map(|(path, entry)| {
+                Some((
+                    path.to_str()?.to_string(),
+                    json!([entry.modified, entry.len, format!("{:016x}", entry.hash)]),
+                ))
+            })
+            .collect();
+        Value::Object(entries)
+    }
+
+    pub fn load() -> Index {
+        fs::read_to_string(pure::expand_home(Path::new(INDEX_PATH)))
+            .ok()
+            .and_then(|raw| serde_json::from_str(&raw).ok())
+            .map(|raw| Index::from_json(&raw))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Option<()> {
+        let path = pure::expand_home(Path::new(INDEX_PATH));
+        fs::create_dir_all(path.parent()?).ok()?;
+        fs::write(path, self.to_json().to_string()).ok()
+    }
+
+    /// Adds the images under `roots`, and brings the other entries up to date:
+    /// files that changed are hashed again, and files that are gone are dropped
+    pub fn update(&mut self, roots: &[PathBuf]) {
+        let mut paths: Vec<PathBuf> = roots.iter().flat_map(|root| image_files(root)).collect();
+        paths.extend(
+            self.entries
+                .keys()
+                .filter(|path| !roots.iter().any(|root| path.starts_with(root)))
+                .cloned(),
+        );
+        let files: Vec<(PathBuf, (u64, u64))> = paths
+            .into_iter()
+            .filter_map(|path| Some((path.clone(), file_stamp(&path)?)))
+            .collect();
+        let (fresh, stale): (Vec<_>, Vec<_>) = files.into_iter().partition(|(path, stamp)| {
+            self.entries
+                .get(path)
+                .is_some_and(|entry| (entry.modified, entry.len) == *stamp)
+        });
+
+        let mut entries: BTreeMap<PathBuf, Entry> = fresh
+            .into_iter()
+            .map(|(path, _)| {
+                let entry = self.entries[&path];
+                (path, entry)
+            })
+            .collect();
+        entries.extend(hash_all(stale));
+        self.entries = entries;
+    }
+
+    /// Hashes a single image into the index, without looking at anything else
+    pub fn add(&mut self, path: &Path) -> Option<()> {
+        let (modified, len) = file_stamp(path)?;
+        let hash = dhash(&renderer::open_image(path)?);
+        self.entries.insert(
+            path.to_path_buf(),
+            Entry {
+                modified,
+                len,
+                hash,
+            },
+        );
+        Some(())
+    }
+
+    /// Indexed images outside of `excluded` that look like `hash`, closest first
+    pub fn matches(&self, hash: u64, excluded: &Path) -> Vec<(PathBuf, u32)> {
+        let mut matches: Vec<(PathBuf, u32)> = self
+            .entries
+            .iter()
+            .filter(|(path, _)| !path.starts_with(excluded))
+            .map(|(path, entry)| (path.clone(), distance(hash, entry.hash)))
+            .filter(|(_, distance)| *distance <= MAX_DISTANCE)
+            .collect();
+        matches.sort_by_key(|(_, distance)| *distance);
+        matches
+    }
+
+    /// Groups of two or more images that are near-duplicates of each other,
+    /// directly or through another image in the group
+    pub fn clusters(&self) -> Vec<Vec<PathBuf>> {
+        let entries: Vec<(&PathBuf, &Entry)> = self.entries.iter().collect();
+        // Union-find, where each image starts as its own group
+        let mut parent: Vec<usize> = (0..entries.len()).collect();
+        fn root(parent: &mut [usize], mut idx: usize) -> usize {
+            while parent[idx] != idx {
+                parent[idx] = parent[parent[idx]];
+                idx = parent[idx];
+            }
+            idx
+        }
+        for i in 0..entries.len() {
+            for j in i + 1..entries.len() {
+                if distance(entries[i].1.hash, entries[j].1.hash) <= MAX_DISTANCE {
+                    let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                    parent[a] = b;
+                }
+            }
+        }
+        let mut groups: BTreeMap<usize, Vec<PathBuf>> = BTreeMap::new();
+        for (idx, (path, _)) in entries.iter().enumerate() {
+            let group = root(&mut parent, idx);
+            groups.entry(group).or_default().push((*path).clone());
+        }
+        groups
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .collect()
+    }
+}
+
+/// Decodes and hashes the files across threads. Files that cannot be decoded are left out
+fn hash_all(files: Vec<(PathBuf, (u64, u64))>) -> Vec<(PathBuf, Entry)> {
+    let threads = thread::available_parallelism().map_or(4, |n| n.get());
+    let chunk_size = files.len().div_ceil(threads).max(1);
+    thread::scope(|scope| {
+        let handles: Vec<_> = files
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .filter_map(|(path, (modified, len))| {
+                            let hash = dhash(&renderer::open_image(path)?);
+                            Some((
+                                path.clone(),
+                                Entry {
+                                    modified: *modified,
+                                    len: *len,
+                                    hash,
+                                },
+                            ))
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_default())
+            .collect()
+    })
+}
+
+/// A directory that can be searched for images as a whole: an absolute path
+/// that is neither the home directory nor one of its parents
+fn dedicated_root(root: PathBuf, home: &Path) -> Option<PathBuf> {
+    let dedicated = root.is_absolute() && !home.starts_with(&root);
+    dedicated.then_some(root)
+}
+
+/// Where downloads go, which is the fixed part of `download_template`. None
+/// if that is not a directory of its own, such as with `~/{artist_name}/...`
+pub fn download_root() -> Option<PathBuf> {
+    let root = pure::template_root(&config::download_template_config());
+    dedicated_root(root, &dirs::home_dir()?)
+}
+
+fn cache_root() -> PathBuf {
+    pure::expand_home(Path::new(KONEKODIR))
+}
+
+/// Downloads that look like the image at `path`, closest first. The cache is
+/// left out, because it always has the image being viewed. The index is brought
+/// up to date with the download directory first, so images saved before it
+/// existed or copied in by hand are found too; only new or changed files are hashed
+pub fn downloaded_duplicates(path: &Path) -> Vec<(PathBuf, u32)> {
+    let hash = match renderer::open_image(path) {
+        Some(image) => dhash(&image),
+        None => return vec![],
+    };
+    let mut index = Index::load();
+    if let Some(downloads) = download_root() {
+        index.update(&[downloads]);
+        index.save();
+    }
+    index
+        .matches(hash, &cache_root())
+        .into_iter()
+        .filter(|(path, _)| path.exists())
+        .collect()
+}
+
+/// Adds a finished download to the index, so later downloads are compared with it
+pub fn record_download(path: &Path) {
+    let mut index = Index::load();
+    if index.add(path).is_some() {
+        index.save();
+    }
+}
+
+/// Prints every group of near-duplicates with at least one downloaded image
+/// in it, or every group at all with `include_cache`. The cache has each post
+/// in more than one size, so groups only in the cache are mostly the same post
+pub fn report(include_cache: bool) {
+    let cache = cache_root();
+    let mut roots = vec![cache.clone()];
+    match download_root() {
+        Some(downloads) => roots.push(downloads),
+        None => println!(
+            "download_template does not save into a directory of its own, so only \
+             images downloaded by koneko are indexed"
+        ),
+    }
+    let names: Vec<String> = roots
+        .iter()
+        .map(|root| root.display().to_string())
+        .collect();
+    println!("Indexing images in {}...", names.join(" and "));
+    let mut index = Index::load();
+    index.update(&roots);
+    if index.save().is_none() {
+        println!("Failed to save the index to {}", INDEX_PATH);
+    }
+
+    let clusters: Vec<Vec<PathBuf>> = index
+        .clusters()
+        .into_iter()
+        .filter(|group| include_cache || group.iter().any(|path| !path.starts_with(&cache)))
+        .collect();
+    if clusters.is_empty() {
+        println!("No near-duplicates found!");
+        return;
+    }
+    println!("Found {} groups of near-duplicates:", clusters.len());
+    for (number, group) in clusters.iter().enumerate() {
+        println!("\n{}.", number + 1);
+        for path in group {
+            println!("   {}", path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::{Rgb, RgbImage};
+
+    /// Different art has a different layout of light and dark
+    fn art(seed: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(180, 160, |x, y| {
+            let value = ((x * (seed + 3) + y * y * seed) / 7 % 256) as u8;
+            Rgb([value, value / 2, 255 - value])
+        }))
+    }
+
+    fn entry(hash: u64) -> Entry {
+        Entry {
+            modified: 0,
+            len: 0,
+            hash,
+        }
+    }
+
+    #[test]
+    fn test_dhash() {
+        let original = dhash(&art(1));
+        let resized = dhash(&art(1).resize_exact(90, 80, FilterType::Triangle));
+        assert!(distance(original, resized) <= MAX_DISTANCE);
+        assert!(distance(original, dhash(&art(5))) > MAX_DISTANCE);
+    }
+
+    #[test]
+    fn test_distance() {
+        assert_eq!(distance(0, 0), 0);
+        assert_eq!(distance(0b1011, 0b0001), 2);
+        assert_eq!(distance(0, u64::MAX), 64);
+    }
+
+    #[test]
+    fn test_is_image() {
+        assert!(is_image(Path::new("76695217_p0.PNG")));
+        assert!(is_image(Path::new("001_hi.jpg")));
+        assert!(!is_image(Path::new("following.json")));
+        assert!(!is_image(Path::new("README")));
+    }
+
+    #[test]
+    fn test_matches_and_clusters() {
+        let hashes = [
+            ("/dl/a.png", 0b1111),
+            ("/dl/b.png", 0b0111),
+            ("/cache/c.jpg", 0b1111_1000_0111),
+            ("/cache/d.jpg", u64::MAX),
+        ];
+        let index = Index {
+            entries: hashes
+                .iter()
+                .map(|(path, hash)| (PathBuf::from(path), entry(*hash)))
+                .collect(),
+        };
+
+        assert_eq!(
+            index.matches(0b1111, Path::new("/cache")),
+            vec![
+                (PathBuf::from("/dl/a.png"), 0),
+                (PathBuf::from("/dl/b.png"), 1)
+            ]
+        );
+        assert!(index.matches(u64::MAX, Path::new("/cache")).is_empty());
+        // c is 9 bits from a but 5 from b, so it only joins through b
+        assert_eq!(
+            index.clusters(),
+            vec![vec![
+                PathBuf::from("/cache/c.jpg"),
+                PathBuf::from("/dl/a.png"),
+                PathBuf::from("/dl/b.png")
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_index_update() {
+        let dir = std::env::temp_dir().join("koneko-test-dedupe");
+        let elsewhere = std::env::temp_dir().join("koneko-test-dedupe-elsewhere");
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_dir_all(&elsewhere);
+        fs::create_dir_all(dir.join("2232374/.thumbnails")).unwrap();
+        fs::create_dir_all(&elsewhere).unwrap();
+        art(5).save(elsewhere.join("c.png")).unwrap();
+        art(5).save(elsewhere.join("unindexed.png")).unwrap();
+        art(1).save(dir.join("a.png")).unwrap();
+        art(1).save(dir.join("2232374/b.png")).unwrap();
+        art(1).save(dir.join("2232374/.thumbnails/b.png")).unwrap();
+        fs::write(dir.join("broken.jpg"), b"not an image").unwrap();
+
+        // Files outside of the roots are only indexed once they are added
+        let mut index = Index::default();
+        index.add(&elsewhere.join("c.png")).unwrap();
+        index.entries.insert(dir.join("deleted.png"), entry(1));
+        index
+            .entries
+            .insert(elsewhere.join("deleted.png"), entry(1));
+        index.update(std::slice::from_ref(&dir));
+        let paths: Vec<&PathBuf> = index.entries.keys().collect();
+        assert_eq!(
+            paths,
+            vec![
+                &dir.join("2232374/b.png"),
+                &dir.join("a.png"),
+                &elsewhere.join("c.png")
+            ]
+        );
+        assert_eq!(index.entries[&dir.join("a.png")].hash, dhash(&art(1)));
+        assert_eq!(index.entries[&elsewhere.join("c.png")].hash, dhash(&art(5)));
+
+        // Entries for unchanged files are kept rather than hashed again
+        index.entries.get_mut(&dir.join("a.png")).unwrap().hash = 42;
+        index.update(std::slice::from_ref(&dir));
+        assert_eq!(index.entries[&dir.join("a.png")].hash, 42);
+
+        assert_eq!(Index::from_json(&index.to_json()), index);
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&elsewhere).unwrap();
+    }
+
+    #[test]
+    fn test_dedicated_root() {
+        let home = Path::new("/home/koneko");
+        assert_eq!(
+            dedicated_root(PathBuf::from("/home/koneko/Downloads"), home),
+            Some(PathBuf::from("/home/koneko/Downloads"))
+        );
+        assert_eq!(
+            dedicated_root(PathBuf::from("/pics"), home),
+            Some(PathBuf::from("/pics"))
+        );
+        assert_eq!(dedicated_root(PathBuf::from("/home/koneko"), home), None);
+        assert_eq!(dedicated_root(PathBuf::from("/home"), home), None);
+        assert_eq!(dedicated_root(PathBuf::from("/"), home), None);
+        assert_eq!(dedicated_root(PathBuf::from(""), home), None);
+        assert_eq!(dedicated_root(PathBuf::from("pics"), home), None);
+    }
+}
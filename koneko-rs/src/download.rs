@@ -0,0 +1,112 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use image::ImageFormat;
+
+use crate::files;
+use crate::pure;
+use crate::renderer;
+use crate::system::{self, system};
+use crate::data::Data;
+
+pub fn download_url(url: &str, filepath: &Path) -> Option<()> {
+    system().fetch(url, filepath)
+}
+
+/// Downloads an image into the given directory, keeping its filename
+pub fn download_url_to_dir(url: &str, dir: &Path) -> Option<()> {
+    let dir = pure::expand_home(dir);
+    fs::create_dir_all(&dir).ok()?;
+    download_url(url, &dir.join(pure::split_backslash_last(url)))
+}
+
+/// Re-encodes a downloaded WebP as a png next to it, and removes the WebP.
+/// Returns where the image is now; other formats are left as they are
+pub fn webp_to_png(filepath: &Path) -> Option<PathBuf> {
+    if renderer::image_format(filepath)? != ImageFormat::WebP {
+        return Some(filepath.to_path_buf());
+    }
+    let pngpath = filepath.with_extension("png");
+    renderer::open_image(filepath)?
+        .save_with_format(&pngpath, ImageFormat::Png)
+        .ok()?;
+    fs::remove_file(filepath).ok()?;
+    Some(pngpath)
+}
+
+/// Downloads every image in the current page, unless they are already on disk
+pub fn init_download(data: &impl Data) {
+    if files::dir_not_empty(data) {
+        return;
+    }
+    files::remove_dir_if_exist(data);
+
+    let download_path = pure::expand_home(&data.download_path());
+    fs::create_dir_all(&download_path).unwrap();
+
+    let handles: Vec<_> = data
+        .all_urls()
+        .into_iter()
+        .zip(data.newnames_with_ext())
+        .map(|(url, newname)| {
+            let filepath = download_path.join(newname.replace('/', ""));
+            system::spawn(move || download_url(&url, &filepath))
+        })
+        .collect();
+
+    for handle in handles {
+        if handle.join().unwrap().is_none() {
+            println!("Failed to download an image!");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::{DynamicImage, RgbImage};
+
+    #[test]
+    fn test_webp_to_png() {
+        let dir = std::env::temp_dir().join("koneko-test-webp-to-png");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let image = DynamicImage::ImageRgb8(RgbImage::new(8, 8));
+
+        let webp = dir.join("81547984_p0.jpg");
+        image.save_with_format(&webp, ImageFormat::WebP).unwrap();
+        let png = webp_to_png(&webp).unwrap();
+        assert_eq!(png, dir.join("81547984_p0.png"));
+        assert_eq!(renderer::image_format(&png), Some(ImageFormat::Png));
+        assert!(!webp.exists());
+
+        let jpg = dir.join("76695217_p0.jpg");
+        image.save_with_format(&jpg, ImageFormat::Jpeg).unwrap();
+        assert_eq!(webp_to_png(&jpg), Some(jpg.clone()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_init_download() {
+        use std::sync::Arc;
+
+        use crate::data::{Gallery, GalleryMode};
+        use crate::system::{with_system, Recorder};
+
+        let main_path = std::env::temp_dir().join("koneko-test-init-download");
+        let _ = fs::remove_dir_all(&main_path);
+        let mut gallery = Gallery::new(main_path.clone(), GalleryMode::TagSearch);
+        let file = fs::File::open("testing/files/mode1.json").unwrap();
+        gallery.update(&serde_json::from_reader(file).unwrap());
+
+        let recorder = Arc::new(Recorder::default());
+        with_system(recorder.clone(), || init_download(&gallery));
+        let calls = recorder.calls.lock().unwrap();
+        assert_eq!(calls.len(), 30);
+        assert!(calls
+            .iter()
+            .all(|call| call.starts_with("fetch https://i.pximg.net/")));
+        fs::remove_dir_all(main_path).unwrap();
+    }
+}
@@ -1,7 +1,10 @@
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
 use std::collections::HashSet;
 use std::path::{Path, PathBuf};
-use std::fs::{self, DirEntry, File};
+use std::fs::{self, DirEntry, File, OpenOptions};
+
+use serde_json::Value;
 
 use crate::pure;
 use crate::KONEKODIR;
@@ -46,9 +49,114 @@ pub fn read_invis(udata: UserData) -> i32 {
     result.parse::<i32>().unwrap()
 }
 
-pub fn remove_dir_if_exist(data: impl Data) {
-    if data.download_path().exists() {
-        fs::remove_dir_all(data.download_path()).unwrap()
+/// Writes a file only the user can read, such as the config with credentials in it
+pub fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    fs::create_dir_all(path.parent().unwrap_or_else(|| Path::new(".")))?;
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // `mode` only applies when the file is created
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(contents)
+}
+
+pub fn remove_dir_if_exist(data: &impl Data) {
+    let download_path = pure::expand_home(&data.download_path());
+    if download_path.exists() {
+        fs::remove_dir_all(download_path).unwrap()
+    }
+}
+
+/// Following pages are cached as `<page number>.json`, next to the directory
+/// with the page's images, so following and unfollowing can edit them in place
+fn following_page_path(main_path: &Path, page_num: i32) -> PathBuf {
+    pure::expand_home(main_path).join(format!("{}.json", page_num))
+}
+
+pub fn read_following_page(main_path: &Path, page_num: i32) -> Option<Value> {
+    let file = File::open(following_page_path(main_path, page_num)).ok()?;
+    serde_json::from_reader(file).ok()
+}
+
+pub fn write_following_page(main_path: &Path, page_num: i32, raw: &Value) {
+    fs::create_dir_all(pure::expand_home(main_path)).unwrap();
+    fs::write(following_page_path(main_path, page_num), raw.to_string()).unwrap();
+}
+
+fn cached_following_pages(main_path: &Path) -> Vec<i32> {
+    let mut pages: Vec<i32> = match fs::read_dir(pure::expand_home(main_path)) {
+        Ok(dir) => dir
+            .map(read_dir_to_string)
+            .filter_map(|name| name.strip_suffix(".json")?.parse().ok())
+            .collect(),
+        Err(_) => vec![],
+    };
+    pages.sort_unstable();
+    pages
+}
+
+pub fn remove_following_cache(main_path: &Path) {
+    let main_path = pure::expand_home(main_path);
+    if main_path.exists() {
+        fs::remove_dir_all(main_path).unwrap()
+    }
+}
+
+/// Replaces the first cached page with a freshly fetched one, and drops its users
+/// from the later cached pages, so a user who moved to the front is not listed twice
+pub fn merge_following_page(main_path: &Path, raw: &Value) {
+    write_following_page(main_path, 1, raw);
+    let user_ids: Vec<String> = match raw["user_previews"].as_array() {
+        Some(previews) => previews
+            .iter()
+            .map(|x| x["user"]["id"].to_string())
+            .collect(),
+        None => return,
+    };
+    for page_num in cached_following_pages(main_path) {
+        if page_num == 1 {
+            continue;
+        }
+        if let Some(mut page) = read_following_page(main_path, page_num) {
+            let mut changed = false;
+            for user_id in &user_ids {
+                changed |= pure::remove_user_preview(&mut page, user_id);
+            }
+            if changed {
+                write_following_page(main_path, page_num, &page);
+            }
+        }
+    }
+}
+
+/// Puts a newly followed user at the front of the first cached page,
+/// unless they are already in one of the cached pages
+pub fn follow_in_cache(main_path: &Path, user_preview: &Value) {
+    let user_id = user_preview["user"]["id"].to_string();
+    let pages = cached_following_pages(main_path);
+    let is_cached = pages.iter().any(|&page_num| {
+        read_following_page(main_path, page_num)
+            .is_some_and(|page| pure::has_user_preview(&page, &user_id))
+    });
+    if is_cached {
+        return;
+    }
+    if let Some(mut page) = read_following_page(main_path, 1) {
+        pure::insert_user_preview(&mut page, user_preview.clone());
+        write_following_page(main_path, 1, &page);
+    }
+}
+
+pub fn unfollow_in_cache(main_path: &Path, user_id: &str) {
+    for page_num in cached_following_pages(main_path) {
+        if let Some(mut page) = read_following_page(main_path, page_num) {
+            if pure::remove_user_preview(&mut page, user_id) {
+                write_following_page(main_path, page_num, &page);
+            }
+        }
     }
 }
 
@@ -60,35 +168,33 @@ pub fn filter_history(path: PathBuf) -> Vec<String> {
         .collect()
 }
 
-fn dir_up_to_date(data: impl Data, dir: &[String]) -> bool {
+fn dir_up_to_date(data: &impl Data, dir: &[String]) -> bool {
     if dir.len() < data.all_names().len() {
         false
     } else {
-        // This is so weird...
-        let mut res = true;
-        for (name, file) in data.all_names().iter().zip(dir) {
-            if file.contains(&name.replace('/', "")) {
-                res = false;
-            }
-        }
-        res
+        data.all_names()
+            .iter()
+            .zip(dir)
+            .all(|(name, file)| file.contains(&name.replace('/', "")))
     }
 }
 
-pub fn dir_not_empty(data: impl Data) -> bool {
-    let mut dir: Vec<String> = fs::read_dir(data.download_path())
+pub fn dir_not_empty(data: &impl Data) -> bool {
+    let download_path = pure::expand_home(&data.download_path());
+    if !download_path.exists() {
+        return false;
+    }
+    // Skips `.koneko`, the `.thumbnails` cache and half-finished downloads
+    let mut dir: Vec<String> = fs::read_dir(download_path)
         .unwrap()
         .map(read_dir_to_string)
+        .filter(|name| !name.starts_with('.'))
         .collect();
 
-    if data.download_path().exists() && dir.iter().len() != 0 {
+    if dir.iter().len() != 0 {
         //TODO: all_names: either data not updated, or request not sent
         dir.sort();
-        if dir[0] == ".koneko" {
-            dir_up_to_date(data, &dir[1..])
-        } else {
-            dir_up_to_date(data, &dir[..])
-        }
+        dir_up_to_date(data, &dir[..])
     } else {
         false
     }
@@ -112,6 +218,19 @@ pub fn filter_dir(modes: Vec<i32>) -> Vec<String> {
     if modes.iter().any(|&x| x == 5) {
         allowed_names.insert("illustfollow");
     }
+    if modes.iter().any(|&x| x == 6) {
+        allowed_names.insert("search_illusts");
+    }
+    if modes.iter().any(|&x| x == 7) {
+        allowed_names.insert("ranking");
+    }
+    if modes.iter().any(|&x| x == 8) {
+        allowed_names.insert("recommended");
+        allowed_names.insert("related");
+    }
+    if modes.iter().any(|&x| x == 9) {
+        allowed_names.insert("bookmarks");
+    }
 
     let res = dirs.map(read_dir_to_string);
     if modes.iter().any(|&x| x == 1) {
@@ -126,3 +245,101 @@ pub fn filter_dir(modes: Vec<i32>) -> Vec<String> {
         res.filter(|x| predicate(x)).collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_private() {
+        let dir = std::env::temp_dir().join("koneko-test-private");
+        let path = dir.join("config.ini");
+        write_private(&path, b"[Credentials]").unwrap();
+        // Already there and readable by everyone
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        write_private(&path, b"[Credentials]\nusername = koneko").unwrap();
+        let meta = fs::metadata(&path).unwrap();
+        assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[Credentials]\nusername = koneko"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_follow_in_cache() {
+        let main_path = std::env::temp_dir().join("koneko-test-following");
+        remove_following_cache(&main_path);
+        let file = File::open("testing/files/mode3.json").unwrap();
+        let page: Value = serde_json::from_reader(file).unwrap();
+        let first = page["user_previews"][0].clone();
+        write_following_page(&main_path, 1, &page);
+        write_following_page(&main_path, 2, &serde_json::json!({"user_previews": []}));
+        assert_eq!(cached_following_pages(&main_path), vec![1, 2]);
+
+        unfollow_in_cache(&main_path, "219621");
+        let cached = read_following_page(&main_path, 1).unwrap();
+        assert!(!pure::has_user_preview(&cached, "219621"));
+
+        follow_in_cache(&main_path, &first);
+        follow_in_cache(&main_path, &first);
+        let cached = read_following_page(&main_path, 1).unwrap();
+        assert_eq!(cached["user_previews"].as_array().unwrap().len(), 30);
+        assert_eq!(cached["user_previews"][0]["user"]["id"], 219621);
+
+        remove_following_cache(&main_path);
+        assert!(read_following_page(&main_path, 1).is_none());
+    }
+
+    #[test]
+    fn test_merge_following_page() {
+        let main_path = std::env::temp_dir().join("koneko-test-merge-following");
+        remove_following_cache(&main_path);
+        let file = File::open("testing/files/mode3.json").unwrap();
+        let page: Value = serde_json::from_reader(file).unwrap();
+        let mut fresh = page.clone();
+        pure::remove_user_preview(&mut fresh, "219621");
+        let mut later = serde_json::json!({"user_previews": []});
+        pure::insert_user_preview(&mut later, page["user_previews"][0].clone());
+        pure::insert_user_preview(&mut later, page["user_previews"][1].clone());
+        write_following_page(&main_path, 1, &page);
+        write_following_page(&main_path, 2, &later);
+        fs::create_dir_all(pure::expand_home(&main_path).join("1")).unwrap();
+
+        merge_following_page(&main_path, &fresh);
+        assert_eq!(read_following_page(&main_path, 1).unwrap(), fresh);
+        let later = read_following_page(&main_path, 2).unwrap();
+        assert_eq!(later["user_previews"].as_array().unwrap().len(), 1);
+        assert!(pure::has_user_preview(&later, "219621"));
+        // The downloaded images are kept
+        assert!(pure::expand_home(&main_path).join("1").exists());
+
+        remove_following_cache(&main_path);
+    }
+
+    #[test]
+    fn test_dir_not_empty() {
+        use crate::data::{Gallery, GalleryMode};
+
+        let main_path = std::env::temp_dir().join("koneko-test-dir-not-empty");
+        let _ = fs::remove_dir_all(&main_path);
+        let mut gallery = Gallery::new(main_path.clone(), GalleryMode::TagSearch);
+        let file = File::open("testing/files/mode1.json").unwrap();
+        gallery.update(&serde_json::from_reader(file).unwrap());
+        assert!(!dir_not_empty(&gallery));
+
+        let download_path = gallery.download_path();
+        fs::create_dir_all(download_path.join(".thumbnails")).unwrap();
+        let names = gallery.newnames_with_ext();
+        for name in &names[1..] {
+            fs::write(download_path.join(name), b"").unwrap();
+        }
+        // One image is missing, so the page is downloaded again
+        assert!(!dir_not_empty(&gallery));
+
+        fs::write(download_path.join(&names[0]), b"").unwrap();
+        assert!(dir_not_empty(&gallery));
+        fs::remove_dir_all(main_path).unwrap();
+    }
+}
@@ -0,0 +1,165 @@
+//! Rules for hiding posts and users, set in the `[filter]` section of the config
+
+use serde_json::Value;
+
+/// pixiv's `illust_ai_type` for posts marked as AI-generated
+const AI_GENERATED: i64 = 2;
+
+#[derive(Clone)]
+pub struct Filter {
+    /// Lowercased; matched against both the tag and its translation
+    pub blocked_tags: Vec<String>,
+    pub blocked_artists: Vec<String>,
+    /// 0 shows only all-ages posts, 1 also shows R-18, 2 also shows R-18G
+    pub max_x_restrict: i64,
+    pub hide_ai_generated: bool,
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter {
+            blocked_tags: vec![],
+            blocked_artists: vec![],
+            max_x_restrict: 2,
+            hide_ai_generated: false,
+        }
+    }
+}
+
+impl Filter {
+    pub fn hides_user(&self, user: &Value) -> bool {
+        self.blocked_artists.contains(&user["id"].to_string())
+    }
+
+    pub fn hides_post(&self, post: &Value) -> bool {
+        self.hides_user(&post["user"])
+            || post["x_restrict"].as_i64().unwrap_or(0) > self.max_x_restrict
+            || (self.hide_ai_generated && post["illust_ai_type"].as_i64() == Some(AI_GENERATED))
+            || self.has_blocked_tag(post)
+    }
+
+    fn has_blocked_tag(&self, post: &Value) -> bool {
+        let tags = match post["tags"].as_array() {
+            Some(tags) => tags,
+            None => return false,
+        };
+        tags.iter()
+            .flat_map(|tag| vec![&tag["name"], &tag["translated_name"]])
+            .filter_map(|name| name.as_str())
+            .any(|name| self.blocked_tags.contains(&name.to_lowercase()))
+    }
+
+    /// Removes hidden posts from a list of illusts, returning the
+    /// original positions of the removed posts
+    pub fn filter_illusts(&self, illusts: &mut Value) -> Vec<usize> {
+        let posts = match illusts.as_array_mut() {
+            Some(posts) => posts,
+            None => return vec![],
+        };
+        let hidden: Vec<usize> = posts
+            .iter()
+            .enumerate()
+            .filter(|(_, post)| self.hides_post(post))
+            .map(|(idx, _)| idx)
+            .collect();
+        let mut idx = 0;
+        posts.retain(|_| {
+            idx += 1;
+            !hidden.contains(&(idx - 1))
+        });
+        hidden
+    }
+
+    /// Removes hidden users from a list of user previews, and hidden posts
+    /// from the previews of the remaining users. Returns how many were removed
+    pub fn filter_user_previews(&self, user_previews: &mut Value) -> usize {
+        let previews = match user_previews.as_array_mut() {
+            Some(previews) => previews,
+            None => return 0,
+        };
+        let before = previews.len();
+        previews.retain(|preview| !self.hides_user(&preview["user"]));
+        let hidden_users = before - previews.len();
+        let hidden_posts: usize = previews
+            .iter_mut()
+            .map(|preview| self.filter_illusts(&mut preview["illusts"]).len())
+            .sum();
+        hidden_users + hidden_posts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    use rstest::*;
+
+    #[fixture]
+    fn gallery_json() -> Value {
+        let file = fs::File::open("testing/files/mode1.json").unwrap();
+        serde_json::from_reader(file).unwrap()
+    }
+
+    #[fixture]
+    fn user_json() -> Value {
+        let file = fs::File::open("testing/files/mode3.json").unwrap();
+        serde_json::from_reader(file).unwrap()
+    }
+
+    #[rstest]
+    fn test_default_filter_hides_nothing(gallery_json: Value, user_json: Value) {
+        let mut illusts = gallery_json["illusts"].clone();
+        let mut previews = user_json["user_previews"].clone();
+        assert!(Filter::default().filter_illusts(&mut illusts).is_empty());
+        assert_eq!(Filter::default().filter_user_previews(&mut previews), 0);
+        assert_eq!(illusts, gallery_json["illusts"]);
+    }
+
+    #[rstest]
+    fn test_filter_illusts(gallery_json: Value) {
+        let mut illusts = gallery_json["illusts"].clone();
+        illusts[1]["x_restrict"] = Value::from(1);
+        illusts[2]["illust_ai_type"] = Value::from(AI_GENERATED);
+        let filter = Filter {
+            max_x_restrict: 0,
+            hide_ai_generated: true,
+            ..Filter::default()
+        };
+        assert_eq!(filter.filter_illusts(&mut illusts), vec![1, 2]);
+        assert_eq!(illusts[1], gallery_json["illusts"][3]);
+    }
+
+    #[test]
+    fn test_hides_post() {
+        let post = serde_json::json!({
+            "user": {"id": 2232374},
+            "tags": [{"name": "水着", "translated_name": "Swimsuit"}],
+            "x_restrict": 0,
+        });
+        let tag = |tag: &str| Filter {
+            blocked_tags: vec![tag.to_string()],
+            ..Filter::default()
+        };
+        assert!(tag("水着").hides_post(&post));
+        assert!(tag("swimsuit").hides_post(&post));
+        assert!(!tag("swim").hides_post(&post));
+
+        let artist = Filter {
+            blocked_artists: vec!["2232374".to_string()],
+            ..Filter::default()
+        };
+        assert!(artist.hides_post(&post));
+    }
+
+    #[rstest]
+    fn test_filter_user_previews(user_json: Value) {
+        let mut previews = user_json["user_previews"].clone();
+        let filter = Filter {
+            blocked_artists: vec!["219621".to_string()],
+            ..Filter::default()
+        };
+        assert_eq!(filter.filter_user_previews(&mut previews), 1);
+        assert_eq!(previews.as_array().unwrap().len(), 29);
+    }
+}
@@ -0,0 +1,195 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use image::{DynamicImage, GenericImageView};
+
+use crate::utils;
+use crate::colors::Theme;
+use crate::config;
+use crate::printer;
+use crate::pure;
+use crate::renderer;
+use crate::thumbnail;
+use crate::data::{Data, UserData};
+
+/// Rows left free below a centered image, for the info line and the prompt
+pub const CENTER_TEXT_ROWS: i32 = 3;
+
+pub fn clear() {
+    renderer::renderer().clear()
+}
+
+/// Shows an image as large as the terminal allows, centered horizontally,
+/// with `bottom_rows` rows left free below it. The cursor is left below the image
+pub fn show_center(path: &Path, bottom_rows: i32) {
+    if let Some(dimensions) = renderer::image_dimensions(path) {
+        let (size, x) = center_box(dimensions, bottom_rows);
+        renderer::renderer().show(path, size, x, 0);
+        printer::move_cursor_xy(0, size.1);
+    }
+}
+
+/// Draws an image to the right of `text_columns` columns of text at the top of the
+/// screen, as tall as its `rows` rows, and leaves the cursor below the text
+pub fn show_beside_text(path: &Path, text_columns: i32, rows: i32) {
+    let x = text_columns + 2;
+    let width = utils::term_width() as i32 - x;
+    // Not worth drawing in a narrow terminal
+    if width >= 8 && !config::text_only_config() {
+        renderer::renderer().show(path, (width, rows), x, 0);
+    }
+    printer::move_cursor_xy(0, rows);
+}
+
+/// Like `show_center`, for the part of `image` that is visible when zoomed
+/// in `zoom` times around `center` (see `pure::zoom_crop`)
+pub fn show_zoomed(image: &DynamicImage, zoom: f64, center: (f64, f64), bottom_rows: i32) {
+    let (x, y, width, height) = pure::zoom_crop(image.dimensions(), zoom, center);
+    let cropped = image.crop_imm(x, y, width, height);
+    let (size, x) = center_box(cropped.dimensions(), bottom_rows);
+    renderer::renderer().show_image(&cropped, size, x, 0);
+    printer::move_cursor_xy(0, size.1);
+}
+
+/// The cells an image of `dimensions` pixels takes up when it is as large as the
+/// terminal allows above `bottom_rows`, and the column to draw it from to center it
+fn center_box(dimensions: (u32, u32), bottom_rows: i32) -> ((i32, i32), i32) {
+    let term_size = (
+        utils::term_width() as i32,
+        utils::term_height() as i32 - bottom_rows,
+    );
+    // The image's aspect ratio in cells, rather than in pixels
+    let (cell_width, cell_height) = utils::cell_size().unwrap_or((10, 20));
+    let (columns, _) = pure::fit_size(
+        (dimensions.0 * cell_height, dimensions.1 * cell_width),
+        (term_size.0 as u32, term_size.1 as u32),
+    );
+    let x = (term_size.0 - columns as i32) / 2;
+    ((columns as i32, term_size.1), x)
+}
+
+/// Rows taken up by the strip of previews, including the page numbers above it
+pub fn previews_height() -> i32 {
+    config::width_padding("height", "x").0 + 1
+}
+
+/// Draws a strip of thumbnails above the text at the bottom of the screen, with
+/// the current page's number highlighted. The cursor is left where text should go,
+/// `image_mode_text_offset` rows from the bottom
+pub fn show_previews(previews: &[PathBuf], current: usize) {
+    let (width, height) = (
+        config::width_padding("width", "x").0,
+        config::width_padding("height", "x").0,
+    );
+    let xcoords = config::xcoords_config(0);
+    let text_row = utils::term_height() as i32 - config::image_text_offset();
+    let y = text_row - previews_height();
+    let renderer = renderer::renderer();
+
+    for (col, number) in pure::preview_window(current, previews.len(), xcoords.len()).enumerate() {
+        printer::move_cursor_xy(xcoords[col], y);
+        if number == current {
+            printer::write(&Theme::current().selected(&(number + 1).to_string()));
+        } else {
+            printer::write(&format!(" {} ", number + 1));
+        }
+        renderer.show_thumbnail(&previews[number], (width, height), xcoords[col], y + 1);
+    }
+    printer::move_cursor_xy(0, text_row);
+}
+
+/// Every image in the download directory, in the order they were numbered in
+pub fn sorted_images(download_path: &Path) -> Vec<PathBuf> {
+    let mut images: Vec<PathBuf> = match fs::read_dir(pure::expand_home(download_path)) {
+        Ok(dir) => dir
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| !path.file_name().unwrap().to_str().unwrap().starts_with('.'))
+            .collect(),
+        Err(_) => vec![],
+    };
+    images.sort();
+    images
+}
+
+/// Draws a page of thumbnails in a grid. If there are labels, each row of
+/// thumbnails is moved down one line and the labels are printed above them.
+pub fn show_instant(data: &impl Data, labels: &[String]) {
+    let ncols = config::ncols_config();
+    let nrows = config::nrows_config();
+    let xcoords = config::xcoords_config(0);
+    let ycoords = config::ycoords_config();
+    let (width, _) = config::width_padding("width", "x");
+    let (height, _) = config::width_padding("height", "x");
+    let page_spacing = config::gallery_page_spacing_config();
+    let print_spacing = config::gallery_print_spacing_config();
+    let label_offset = if labels.is_empty() { 0 } else { 1 };
+    let renderer = renderer::renderer();
+    let mut bottom = 0;
+
+    let images = sorted_images(&data.download_path());
+    thumbnail::cache_all(&images, renderer.pixel_bounds((width, height)));
+    for (number, image) in images.iter().enumerate() {
+        if number != 0 && (number as i32).rem_euclid(ncols * nrows) == 0 {
+            println!("{}", "\n".repeat(page_spacing as usize));
+        }
+        let (col, row) = pure::grid_position(number as i32, ncols, nrows);
+        if col == 0 && !labels.is_empty() {
+            let end = (number + ncols as usize).min(labels.len());
+            printer::move_cursor_xy(0, ycoords[row]);
+            printer::write(&pure::labels_line(
+                &print_spacing,
+                &labels[number.min(end)..end],
+            ));
+        }
+        renderer.show_thumbnail(
+            image,
+            (width, height),
+            xcoords[col],
+            ycoords[row] + label_offset,
+        );
+        bottom = ycoords[row] + label_offset + height;
+    }
+    // Renderers don't move the cursor past the image, so text would go over it
+    printer::move_cursor_xy(0, bottom);
+}
+
+/// Draws a page of users, one per row: the user's number and name, and
+/// below it their profile picture followed by their preview illusts
+pub fn show_users(udata: &UserData) {
+    let nrows = config::nrows_config();
+    let xcoords = config::xcoords_config(0);
+    let ycoords = config::ycoords_config();
+    let (width, _) = config::width_padding("width", "x");
+    let (height, _) = config::width_padding("height", "x");
+    let page_spacing = config::users_page_spacing_config();
+    let renderer = renderer::renderer();
+
+    let images = sorted_images(&udata.download_path());
+    thumbnail::cache_all(&images, renderer.pixel_bounds((width, height)));
+    let splitpoint = (udata.splitpoint as usize).min(images.len());
+    let (profile_pics, previews) = images.split_at(splitpoint);
+    let mut previews = previews.iter();
+    let mut bottom = 0;
+
+    for (number, (profile_pic, count)) in
+        profile_pics.iter().zip(udata.preview_counts()).enumerate()
+    {
+        let row = number.rem_euclid(nrows as usize);
+        if number != 0 && row == 0 {
+            println!("{}", "\n".repeat(page_spacing as usize));
+        }
+        printer::move_cursor_xy(0, ycoords[row]);
+        printer::write(&pure::prefix_artist_name(
+            &udata.names()[number],
+            number as i32,
+        ));
+        renderer.show_thumbnail(profile_pic, (width, height), xcoords[0], ycoords[row] + 1);
+        for (col, preview) in previews.by_ref().take(count).enumerate() {
+            if let Some(x) = xcoords.get(col + 1) {
+                renderer.show_thumbnail(preview, (width, height), *x, ycoords[row] + 1);
+            }
+        }
+        bottom = ycoords[row] + 1 + height;
+    }
+    printer::move_cursor_xy(0, bottom);
+}
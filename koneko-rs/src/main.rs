@@ -1,19 +1,306 @@
 #![allow(dead_code)] // Temporary, remove later
 #[macro_use]
 mod utils;
+mod api;
+mod auth;
+mod calibrate;
 mod colors;
 mod config;
 mod data;
+mod dedupe;
+mod download;
 mod files;
+mod filter;
+mod lscat;
+mod manual;
+#[cfg(test)]
+mod mock;
 mod printer;
+mod prompt;
 mod pure;
+mod renderer;
 mod screens;
+mod secret;
+mod system;
+mod thumbnail;
 mod ui;
 
+use std::env;
+use std::path::Path;
+use std::process;
+
+use chrono::NaiveDate;
+
+use api::{Api, RankingMode, Restrict, SearchSort, SearchTarget};
+use data::{Data, GalleryMode, UserMode};
+use manual::Page;
+
 const KONEKODIR: &str = "~/.local/share/koneko/cache";
+#[allow(non_upper_case_globals)]
 const __version__: &str = "0.0.1";
 
 fn main() {
-    printer::write("Hello, world!");
-    //utils::open_in_browser("76695217")
+    let args = match config::take_overrides(env::args().skip(1).collect()) {
+        Ok((overrides, args)) => {
+            config::set_overrides(overrides);
+            args
+        }
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(2);
+        }
+    };
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["config", "show"] => {
+            config::show_config();
+            return;
+        }
+        ["config", ..] => {
+            eprintln!("Usage: koneko config show");
+            process::exit(2);
+        }
+        ["dedupe"] => {
+            dedupe::report(false);
+            return;
+        }
+        ["dedupe", "--all"] => {
+            dedupe::report(true);
+            return;
+        }
+        ["dedupe", ..] => {
+            eprintln!("Usage: koneko dedupe [--all]");
+            process::exit(2);
+        }
+        _ => (),
+    }
+
+    let mut creds = config::begin_config();
+    let api = match Api::login(&creds) {
+        Some(api) => api,
+        None => {
+            println!("Failed to login with the saved login! Please login again");
+            creds = config::ask_login();
+            Api::login(&creds).unwrap_or_else(|| {
+                println!("Failed to login! Please check your connection and try again");
+                process::exit(1)
+            })
+        }
+    };
+    if creds.your_id.is_empty() {
+        creds.your_id = api.user_id.clone();
+    }
+    config::watch_config();
+    utils::handle_missing_pics();
+    main_loop(&api, &creds, args.first().copied());
+}
+
+/// `first_command` is a main menu key given on the command line
+fn main_loop(api: &Api, creds: &config::Credentials, first_command: Option<&str>) {
+    let mut printmessages = true;
+    let mut first_command = first_command.map(str::to_string);
+    loop {
+        let command = first_command
+            .take()
+            .unwrap_or_else(|| screens::begin_prompt(printmessages));
+        printmessages = true;
+        match command.trim() {
+            "6" => tag_search_mode(api),
+            "7" => ranking_mode(api),
+            "8" => recommended_mode(api),
+            "3" => following_mode(api, &creds.your_id),
+            "9" => bookmarks_mode(api, &creds.your_id),
+            "1" | "2" | "4" | "5" | "f" => println!("This mode has not been ported yet!"),
+            "?" => screens::info_screen_loop(),
+            "m" => screens::show_man_loop(Page::Main),
+            "c" => screens::clear_cache_loop(),
+            "q" => prompt::ask_quit(),
+            _ => {
+                println!("Invalid command!");
+                printmessages = false;
+            }
+        }
+    }
+}
+
+fn tag_search_mode(api: &Api) {
+    let tag = prompt::input("Enter the tag to search for: ");
+    if tag.is_empty() {
+        return;
+    }
+    let search_target = SearchTarget::from_key(&prompt::input(
+        "Match tags [e]xactly, [p]artially, or search [t]itles and captions? (default: p) ",
+    ));
+    let sort = SearchSort::from_key(&prompt::input(
+        "Show [n]ewest or [o]ldest first? (default: n) ",
+    ));
+
+    let raw = match api.search_illust(&tag, &search_target, &sort) {
+        Some(raw) => raw,
+        None => {
+            println!("Search request failed!");
+            return;
+        }
+    };
+
+    let mut gdata = data::Gallery::new(
+        Path::new(KONEKODIR)
+            .join("search_illusts")
+            .join(tag.replace('/', "")),
+        GalleryMode::TagSearch,
+    );
+    gdata.update(&raw);
+    if is_empty(&gdata, &format!("No illustrations found for {}!", tag)) {
+        return;
+    }
+    prompt::gallery_like_prompt(api, &mut gdata);
+}
+
+/// Prints `message` if the first page has no posts, or how many were hidden
+/// if the filter hid all of them
+fn is_empty(gdata: &data::Gallery, message: &str) -> bool {
+    if !gdata.all_urls().is_empty() {
+        return false;
+    }
+    if gdata.hidden_count() == 0 {
+        println!("{}", message);
+    } else {
+        println!("Every result was hidden by the filter!");
+        printer::print_hidden(gdata.hidden_count());
+    }
+    true
+}
+
+fn ranking_mode(api: &Api) {
+    let mode = RankingMode::from_key(&prompt::input(
+        "Show the [d]aily, [w]eekly, [m]onthly, [r]ookie or [o]riginal ranking? (default: d) ",
+    ));
+    let date = prompt::input("Enter a date in YYYY-MM-DD, or leave blank for the latest: ");
+    if !date.is_empty() && NaiveDate::parse_from_str(&date, "%Y-%m-%d").is_err() {
+        println!("Invalid date!");
+        return;
+    }
+    let date = if date.is_empty() { None } else { Some(date) };
+
+    let raw = match api.illust_ranking(&mode, date.as_deref()) {
+        Some(raw) => raw,
+        None => {
+            println!("Ranking request failed!");
+            return;
+        }
+    };
+
+    let mut gdata = data::Gallery::new(
+        Path::new(KONEKODIR)
+            .join("ranking")
+            .join(mode.as_str())
+            .join(date.as_deref().unwrap_or("latest")),
+        GalleryMode::Ranking,
+    );
+    gdata.update(&raw);
+    if is_empty(&gdata, "This ranking is empty!") {
+        return;
+    }
+    prompt::gallery_like_prompt(api, &mut gdata);
+}
+
+fn recommended_mode(api: &Api) {
+    let raw = match api.illust_recommended() {
+        Some(raw) => raw,
+        None => {
+            println!("Recommended illusts request failed!");
+            return;
+        }
+    };
+
+    let mut gdata = data::Gallery::new(
+        Path::new(KONEKODIR).join("recommended"),
+        GalleryMode::Recommended,
+    );
+    gdata.update(&raw);
+    if is_empty(&gdata, "No recommended illustrations found!") {
+        return;
+    }
+    prompt::gallery_like_prompt(api, &mut gdata);
+}
+
+fn bookmarks_mode(api: &Api, your_id: &str) {
+    let user_id = if your_id.is_empty() {
+        prompt::input("Enter your pixiv ID: ")
+    } else {
+        your_id.to_string()
+    };
+    let restrict = Restrict::from_key(&prompt::input(
+        "View [1] public or [2] private bookmarks? (default: 1) ",
+    ));
+    let tag = prompt::input("Enter a bookmark tag to filter by, or leave blank for all: ");
+    let tag = if tag.is_empty() { None } else { Some(tag) };
+
+    let raw = match api.user_bookmarks_illust(&user_id, restrict, tag.as_deref()) {
+        Some(raw) => raw,
+        None => {
+            println!("Bookmarks request failed!");
+            return;
+        }
+    };
+
+    let mut gdata = data::Gallery::new(
+        data::bookmarks_path(restrict, tag.as_deref()),
+        GalleryMode::Bookmarks {
+            user_id,
+            restrict,
+            tag,
+        },
+    );
+    gdata.update(&raw);
+    if is_empty(&gdata, "No bookmarks found!") {
+        return;
+    }
+    prompt::gallery_like_prompt(api, &mut gdata);
+}
+
+fn following_mode(api: &Api, your_id: &str) {
+    let user_id = if your_id.is_empty() {
+        prompt::input("Enter your pixiv ID: ")
+    } else {
+        your_id.to_string()
+    };
+    let restrict = Restrict::from_key(&prompt::input(
+        "View [1] public or [2] private following? (default: 1) ",
+    ));
+    let main_path = data::following_path(&user_id, restrict);
+
+    // Following and unfollowing keep the cached pages up to date, but the
+    // list may have changed on pixiv too, so the first page is fetched again
+    // and merged into the cache, keeping the later pages and the images
+    let raw = match api.user_following(&user_id, restrict) {
+        Some(raw) => {
+            files::merge_following_page(&main_path, &raw);
+            raw
+        }
+        None => match files::read_following_page(&main_path, 1) {
+            Some(raw) => {
+                println!("Following request failed! Showing the list from the last visit");
+                raw
+            }
+            None => {
+                println!("Following request failed!");
+                return;
+            }
+        },
+    };
+
+    let mut udata = data::User {
+        page_num: 1,
+        main_path,
+        offset: 0,
+        mode: UserMode::Following { user_id, restrict },
+        filter: config::filter_config(),
+    }
+    .update(&raw);
+    if udata.names().is_empty() {
+        println!("Not following anyone!");
+        return;
+    }
+    prompt::user_prompt(api, &mut udata);
 }
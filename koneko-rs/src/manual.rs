@@ -0,0 +1,221 @@
+//! The built-in manual, one page for each screen, and the logic of the pager
+//! it is shown in when it does not fit in the terminal
+
+use std::ops::Range;
+
+/// Which screen the manual was opened from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Page {
+    Main,
+    Gallery,
+    Image,
+    User,
+    FullRes,
+}
+
+impl Page {
+    pub fn doc(self) -> &'static str {
+        match self {
+            Page::Main => MAIN,
+            Page::Gallery => GALLERY,
+            Page::Image => IMAGE,
+            Page::User => USER,
+            Page::FullRes => FULL_RES,
+        }
+    }
+}
+
+const MAIN: &str = "\
+MAIN MENU
+  1   View an artist's illustrations
+  2   Open a pixiv post
+  3   View the artists you follow
+  4   Search for artists
+  5   View the latest illustrations of the artists you follow
+  6   Search for illustrations by tag
+  7   View the daily, weekly, monthly, rookie or original rankings
+  8   View illustrations recommended for you
+  9   View your public or private bookmarks
+  f   Frequent modes and user inputs
+  ?   Info
+  m   This manual
+  c   Clear the cache of thumbnails and images
+  q   Quit
+
+Modes that show illustrations open the gallery, and modes that show
+artists open the user view. Press m in any of them for its own manual.
+
+COMMAND LINE
+  koneko [--set section.key=value]... [command]
+      Starts with a main menu command, such as 9 for your bookmarks.
+      --set overrides a setting in the config for this run only, like
+      the KONEKO_<SECTION>_<KEY> environment variables do
+  koneko config show
+      Prints every setting, and whether it comes from --set, the
+      environment, the config or the default
+  koneko dedupe [--all]
+      Lists groups of near-duplicate images, such as reposts of the same
+      art, that include a download. --all also lists groups only in the
+      cache. Images are indexed in ~/.local/share/koneko/hashes.json, along
+      with the download directory unless it is the home directory
+
+CONFIG
+  The config is at ~/.config/koneko/config.ini, and is written with every
+  setting and a comment about it on the first run. Changes to it are
+  picked up while koneko is running, and the screen is drawn again.";
+
+const GALLERY: &str = "\
+GALLERY
+Images are picked either by their coordinates on the screen, {x}{y},
+where {x} is the column and {y} is the row, or by their number, {n},
+which is the number above each image. Both are always two digits.
+
+  {x}{y}    View the image at (x, y); 12 is the first column, second row
+  i{n}      View the nth image; i05 is the image numbered 5
+  d{x}{y}   Download the image at (x, y)
+  D{n}      Download the nth image
+  o{x}{y}   Open the image at (x, y) in the browser
+  O{n}      Open the nth image in the browser
+  s{x}{y}   Add the image at (x, y) to your bookmarks
+  S{n}      Add the nth image to your bookmarks
+  u{x}{y}   Remove the image at (x, y) from your bookmarks
+  U{n}      Remove the nth image from your bookmarks
+
+  n         Next page
+  p         Previous page
+  r         Reload the page, and download the thumbnails again
+  t         Switch between public and private bookmarks
+  h         Show the keys in short
+  m         This manual
+  b         Back to the main menu
+  q         Quit, after confirming
+
+Bookmarking asks whether the bookmark is public or private, and for
+tags separated by spaces. Downloads go where download_template in the
+[misc] section says, which is ~/Downloads unless it is changed.";
+
+const IMAGE: &str = "\
+IMAGE VIEW
+Posts with many pages show one page at a time. With image_mode_previews
+on in the [experimental] section, the next pages are shown below it.
+
+  n     Next page of the post
+  p     Previous page of the post
+  {n}   Jump to page n of the post
+  d     Download the page, after asking if it looks like one already
+        downloaded
+  a     Download every page of the post
+  o     Open the post in the browser
+  v     Open the page in the image viewer, set by image_viewer_command
+  f     Show the page in full resolution, where it can be zoomed
+  r     View illustrations related to the post
+  s     Add the post to your bookmarks
+  u     Remove the post from your bookmarks
+  F     Follow the artist
+  U     Unfollow the artist
+  h     Show the keys in short
+  m     This manual
+  b     Back to the gallery
+  q     Quit, after confirming";
+
+const USER: &str = "\
+USER VIEW
+Each artist is shown with their name and previews of their latest
+illustrations. Artists are picked by their number, {n}, which is
+always two digits.
+
+  {n}       View the illustrations of the nth artist; 03 is artist 3
+  f{n}      Follow the nth artist
+  u{n}      Unfollow the nth artist
+
+  n         Next page
+  p         Previous page
+  r         Reload the page, and download the previews again
+  h         Show the keys in short
+  m         This manual
+  b         Back to the main menu
+  q         Quit, after confirming
+
+Following asks whether to follow publicly or privately. Artists hidden
+by the [filter] section of the config are not counted.";
+
+const FULL_RES: &str = "\
+FULL RESOLUTION VIEW
+The original image, downloaded in full and fitted to the screen. Every
+key in a command is applied in turn, so lll pans right three times and
+++ zooms in twice.
+
+  +     Zoom in; = works too
+  -     Zoom out
+  0     Fit the whole image to the screen again
+  h     Pan left
+  j     Pan down
+  k     Pan up
+  l     Pan right
+  ?     Show the keys in short
+  m     This manual
+  b     Back to the image view
+  q     Quit, after confirming";
+
+/// The lines shown when the pager is scrolled to `top`
+pub fn visible(len: usize, top: usize, height: usize) -> Range<usize> {
+    top..(top + height).min(len)
+}
+
+/// Keeps the last page full instead of scrolling past the end
+pub fn clamp_top(top: usize, len: usize, height: usize) -> usize {
+    top.min(len.saturating_sub(height))
+}
+
+/// The first line from `start` that contains `query`, ignoring case,
+/// wrapping around to the top
+pub fn search(lines: &[&str], query: &str, start: usize) -> Option<usize> {
+    let query = query.to_lowercase();
+    (0..lines.len())
+        .map(|offset| (start + offset) % lines.len())
+        .find(|idx| lines[*idx].to_lowercase().contains(&query))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_visible() {
+        assert_eq!(visible(100, 0, 20), 0..20);
+        assert_eq!(visible(100, 90, 20), 90..100);
+        assert_eq!(visible(5, 0, 20), 0..5);
+    }
+
+    #[test]
+    fn test_clamp_top() {
+        assert_eq!(clamp_top(50, 100, 20), 50);
+        assert_eq!(clamp_top(95, 100, 20), 80);
+        assert_eq!(clamp_top(3, 5, 20), 0);
+    }
+
+    #[test]
+    fn test_search() {
+        let lines = ["GALLERY", "  n  Next page", "  p  Previous page"];
+        assert_eq!(search(&lines, "page", 0), Some(1));
+        assert_eq!(search(&lines, "page", 2), Some(2));
+        assert_eq!(search(&lines, "NEXT", 2), Some(1));
+        assert_eq!(search(&lines, "gallery", 1), Some(0));
+        assert_eq!(search(&lines, "bookmark", 0), None);
+        assert_eq!(search(&[], "page", 0), None);
+    }
+
+    #[test]
+    fn test_docs() {
+        let pages = [
+            Page::Main,
+            Page::Gallery,
+            Page::Image,
+            Page::User,
+            Page::FullRes,
+        ];
+        for page in pages.iter() {
+            assert!(page.doc().lines().all(|line| line.chars().count() <= 80));
+        }
+    }
+}
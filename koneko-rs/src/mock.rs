@@ -0,0 +1,101 @@
+//! A local stand-in for the pixiv app-api, used by tests
+
+use std::thread;
+use std::sync::{Arc, Mutex};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::api::Api;
+
+pub struct Request {
+    pub method: String,
+    /// Path including the query string
+    pub path: String,
+    pub body: String,
+}
+
+pub struct MockApi {
+    pub url: String,
+    pub requests: Arc<Mutex<Vec<Request>>>,
+}
+
+type Handler = dyn Fn(&Request) -> (u16, String) + Send + Sync;
+
+impl MockApi {
+    /// Serves every request with `handler`, which returns the status code and body
+    pub fn start<F>(handler: F) -> MockApi
+    where
+        F: Fn(&Request) -> (u16, String) + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let log = Arc::clone(&requests);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => respond(stream, &*handler, &log),
+                    Err(_) => return,
+                }
+            }
+        });
+        MockApi { url, requests }
+    }
+
+    pub fn api(&self) -> Api {
+        Api::new(&self.url, "token")
+    }
+
+    pub fn paths(&self) -> Vec<String> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|r| format!("{} {}", r.method, r.path))
+            .collect()
+    }
+}
+
+fn respond(stream: TcpStream, handler: &Handler, log: &Mutex<Vec<Request>>) {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("").to_string();
+    let path = parts.next().unwrap_or("").to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).unwrap();
+        if header.trim().is_empty() {
+            break;
+        }
+        let mut kv = header.splitn(2, ':');
+        if kv.next().unwrap().eq_ignore_ascii_case("content-length") {
+            content_length = kv.next().unwrap().trim().parse().unwrap();
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).unwrap();
+
+    let request = Request {
+        method,
+        path,
+        body: String::from_utf8(body).unwrap(),
+    };
+    let (status, response) = handler(&request);
+    log.lock().unwrap().push(request);
+
+    let mut stream = reader.into_inner();
+    write!(
+        stream,
+        "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        response.len(),
+        response
+    )
+    .unwrap();
+}
@@ -1,8 +1,9 @@
-use std::process::Command;
 use std::io::{self, Write};
 
 use serde_json::Value;
 
+use crate::pure;
+use crate::system::system;
 use crate::utils;
 use crate::colors::*;
 
@@ -13,18 +14,39 @@ pub fn write(value: &str) {
 
 pub fn move_cursor_up(num: i32) {
     if num > 0 {
-        write(&format!("\033[{}A", num))
+        write(&format!("\x1b[{}A", num))
     }
 }
 
 pub fn move_cursor_down(num: i32) {
     if num > 0 {
-        write(&format!("\033[{}B", num))
+        write(&format!("\x1b[{}B", num))
     }
 }
 
+/// Moves the cursor to a cell on the screen, where (0, 0) is the top left
+pub fn move_cursor_xy(x: i32, y: i32) {
+    write(&format!("\x1b[{};{}H", y + 1, x + 1))
+}
+
 pub fn erase_line() {
-    write(&"\033[K")
+    write("\x1b[K")
+}
+
+/// Shown after the config is reloaded, instead of failing on a bad setting
+pub fn print_config_status(errors: &[String]) {
+    if errors.is_empty() {
+        println!("Reloaded the config");
+    }
+    for error in errors {
+        println!("Config error: {}", error);
+    }
+}
+
+pub fn print_hidden(hidden: usize) {
+    if hidden > 0 {
+        println!("{} hidden", hidden)
+    }
 }
 
 pub fn print_cols(spacings: Vec<i32>, ncols: i32) {
@@ -80,7 +102,7 @@ pub fn maybe_print_user_info(actions: Vec<i32>, user_info_xcoord: i32) {
 }
 
 pub fn print_doc(doc: &str) {
-    Command::new("clear").spawn();
+    system().clear();
     let number_of_newlines = doc.matches('\n').count() as u16;
     let bottom = utils::term_height() - (number_of_newlines + 2);
     move_cursor_down(bottom as i32);
@@ -89,9 +111,10 @@ pub fn print_doc(doc: &str) {
 
 #[allow(non_snake_case)]
 pub fn print_multiple_imgs(illusts_json: &Value) {
-    let HASHTAG = format!("{}#", RED);
-    let HAS = format!("{} has {}", RESET, BLUE);
-    let OF_PAGES = format!("{} pages", RESET);
+    let theme = Theme::current();
+    let HASHTAG = format!("{}#", theme.bracket);
+    let HAS = format!("{} has {}", theme.reset, theme.number);
+    let OF_PAGES = format!("{} pages", theme.reset);
 
     let mut i = 0;
     loop {
@@ -108,6 +131,32 @@ pub fn print_multiple_imgs(illusts_json: &Value) {
     print!("\n");
 }
 
+pub fn print_gallery_text(illusts_json: &Value, ncols: i32, labels: &[String]) {
+    let mut i = 0;
+    while !illusts_json[i].is_null() {
+        let number = i as i32;
+        let label = labels.get(i).map_or("", |x| x.as_str());
+        println!(
+            "{}",
+            pure::gallery_text_line(
+                number,
+                pure::number_to_coords(number, ncols),
+                &illusts_json[i],
+                label
+            )
+        );
+        i += 1
+    }
+}
+
+pub fn print_users_text(user_previews: &Value) {
+    let mut i = 0;
+    while !user_previews[i].is_null() {
+        println!("{}", pure::user_text_line(i as i32, &user_previews[i]));
+        i += 1
+    }
+}
+
 pub fn update_gallery_info(spacings: Vec<i32>, ncols: i32, current_selection: i32) {
     move_cursor_up(2);
     erase_line();
@@ -129,10 +178,47 @@ pub fn update_user_info(spacing: i32) {
     move_cursor_up(2); // so go back to the top
 }
 
+pub fn gallery_help() {
+    println!("");
+    println!(
+        "{}{}{}{}{}{}{}{}{}",
+        base1().concat(),
+        base2().concat(),
+        s(),
+        " bookmark image; ",
+        u(),
+        " unbookmark image;\n",
+        "view ",
+        m(),
+        "anual\n"
+    )
+}
+
+pub fn full_res_help() {
+    println!("");
+    println!(
+        "{}{}{}{}{}{}{}{}{}{}{}{}{}{}",
+        plus(),
+        " zoom in; ",
+        minus(),
+        " zoom out; ",
+        zero(),
+        " fit to screen; ",
+        hjkl(),
+        " pan left, down, up, right;\n",
+        b(),
+        "ack; ",
+        q(),
+        "uit (with confirmation); view ",
+        m(),
+        "anual\n"
+    )
+}
+
 pub fn image_help() {
     println!("");
     println!(
-        "{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}",
+        "{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}",
         b(),
         "ack; ",
         n(),
@@ -140,12 +226,26 @@ pub fn image_help() {
         p(),
         "revious image; ",
         d_(),
-        "ownload image;",
+        "ownload image; ",
+        a_(),
+        "ll pages download;",
         o_(),
-        "pen image in browser;\n",
+        "pen image in browser; ",
+        v(),
+        "iew image in image viewer;\n",
         "show image in",
         f(),
         "ull res; ",
+        r(),
+        "elated illusts; ",
+        s_(),
+        "ave to bookmarks; ",
+        u_(),
+        "nbookmark;\n",
+        shift_f(),
+        "ollow artist; ",
+        shift_u(),
+        "nfollow artist; ",
         q(),
         "uit (with confirmation); ",
         "view ",
@@ -157,10 +257,14 @@ pub fn image_help() {
 pub fn user_help() {
     println!("");
     println!(
-        "{}{}{}{}{}{}{}{}{}{}{}{}{}{}",
+        "{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}{}",
         "view ",
         BLUE_N(),
         "th artist's illusts ",
+        f_n(),
+        " follow nth artist; ",
+        u_n(),
+        " unfollow nth artist;\n",
         n(),
         "ext page; ",
         p(),
@@ -0,0 +1,407 @@
+use std::io::{self, Write};
+use std::process;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::terminal;
+use image::{DynamicImage, GenericImageView};
+use scopeguard::defer;
+
+use crate::api::{Api, Restrict};
+use crate::config;
+use crate::data;
+use crate::lscat;
+use crate::manual::Page;
+use crate::printer;
+use crate::pure;
+use crate::screens;
+use crate::utils;
+
+pub fn input(message: &str) -> String {
+    print!("{}", message);
+    io::stdout().flush().unwrap();
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).unwrap();
+    answer.trim().to_string()
+}
+
+/// How often the config is checked for changes while waiting for input
+const RELOAD_POLL_MS: i32 = 500;
+
+/// Whether a line can be read from stdin without blocking, waiting up to `timeout_ms`
+fn stdin_ready(timeout_ms: i32) -> bool {
+    let mut fd = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    match unsafe { libc::poll(&mut fd, 1, timeout_ms) } {
+        0 => false,
+        // Resizing the terminal interrupts the poll
+        -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => false,
+        // Any other error is left to read_line, which blocks like `input` does
+        _ => true,
+    }
+}
+
+/// Like `input`, but gives up with None when the config is changed,
+/// so that the screen can be drawn again with the new settings
+pub fn input_or_reload(message: &str) -> Option<String> {
+    print!("{}", message);
+    io::stdout().flush().unwrap();
+    loop {
+        if stdin_ready(RELOAD_POLL_MS) {
+            let mut answer = String::new();
+            io::stdin().read_line(&mut answer).unwrap();
+            return Some(answer.trim().to_string());
+        }
+        if config::config_changed() {
+            println!();
+            return None;
+        }
+    }
+}
+
+/// Like `input`, but what is typed is not shown. Falls back to
+/// `input` if stdin is not a terminal
+pub fn hidden_input(message: &str) -> String {
+    if terminal::enable_raw_mode().is_err() {
+        return input(message);
+    }
+    print!("{}", message);
+    io::stdout().flush().unwrap();
+    let mut answer = String::new();
+    {
+        defer! {
+            let _ = terminal::disable_raw_mode();
+        };
+        loop {
+            let KeyEvent { code, modifiers } = match event::read() {
+                Ok(Event::Key(key)) => key,
+                Ok(_) => continue,
+                Err(_) => break,
+            };
+            match code {
+                KeyCode::Enter => break,
+                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => {
+                    let _ = terminal::disable_raw_mode();
+                    println!();
+                    process::exit(1);
+                }
+                KeyCode::Char(c) => answer.push(c),
+                KeyCode::Backspace => {
+                    answer.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+    println!();
+    answer
+}
+
+pub fn ask_quit() {
+    match input("\nAre you sure you want to exit? [Y/n] ").as_str() {
+        "y" | "Y" | "" => process::exit(0),
+        _ => println!("Exit cancelled"),
+    }
+}
+
+fn ask_bookmark_options() -> (Restrict, Vec<String>) {
+    let restrict = Restrict::from_key(&input(
+        "Bookmark as [1] public or [2] private? (default: 1) ",
+    ));
+    let tags = input("Enter bookmark tags separated by spaces, or leave blank: ")
+        .split_whitespace()
+        .map(|tag| tag.to_string())
+        .collect();
+    (restrict, tags)
+}
+
+fn ask_follow_restrict() -> Restrict {
+    Restrict::from_key(&input(
+        "Follow [1] publicly or [2] privately? (default: 1) ",
+    ))
+}
+
+fn keyseqs(command: &str) -> Vec<String> {
+    command.chars().map(|c| c.to_string()).collect()
+}
+
+/// For commands like `o23` and `O12`: a lowercase letter takes
+/// coordinates, an uppercase letter or `i` takes the image number.
+fn post_number(keyseqs: Vec<&str>) -> Option<i32> {
+    if keyseqs.len() != 3 || !pure::all_isdigit(keyseqs[1..].to_vec()) {
+        return None;
+    }
+    let letter = keyseqs[0];
+    if letter == "i" || letter.to_uppercase() == letter {
+        Some(pure::concat_seq_to_int(keyseqs, 1))
+    } else {
+        utils::seq_coords_to_int(keyseqs[1..].to_vec())
+    }
+}
+
+fn valid_post(gdata: &data::Gallery, number: Option<i32>) -> Option<i32> {
+    let number = number?;
+    if gdata.post_json(number).is_null() {
+        None
+    } else {
+        Some(number)
+    }
+}
+
+pub fn gallery_like_prompt(api: &Api, gdata: &mut data::Gallery) {
+    gdata.show_page();
+    loop {
+        let command = match input_or_reload("Enter a gallery command: ") {
+            Some(command) => command,
+            None => {
+                gdata.show_page();
+                printer::print_config_status(&config::config_errors());
+                continue;
+            }
+        };
+        let keyseqs = keyseqs(&command);
+        let keyseqs: Vec<&str> = keyseqs.iter().map(|s| s.as_str()).collect();
+
+        match keyseqs.as_slice() {
+            ["n"] => gdata.next_page(api),
+            ["p"] => gdata.previous_page(),
+            ["r"] => gdata.reload(),
+            ["t"] => gdata.toggle_restrict(api),
+            ["h"] => printer::gallery_help(),
+            ["m"] => {
+                screens::show_man_loop(Page::Gallery);
+                gdata.show_page();
+            }
+            ["q"] => ask_quit(),
+            ["b"] => return,
+            [_, _] if pure::all_isdigit(keyseqs.clone()) => {
+                match valid_post(gdata, utils::seq_coords_to_int(keyseqs.clone())) {
+                    Some(number) => gdata.view_image(api, number),
+                    None => println!("Invalid number!"),
+                }
+            }
+            [letter, _, _] => {
+                let number = match valid_post(gdata, post_number(keyseqs.clone())) {
+                    Some(number) => number,
+                    None => {
+                        println!("Invalid command! Press h to show help");
+                        continue;
+                    }
+                };
+                match letter.to_lowercase().as_str() {
+                    "i" => gdata.view_image(api, number),
+                    "o" => gdata.open_link(number),
+                    "d" => gdata.download_image(number),
+                    "s" => {
+                        let (restrict, tags) = ask_bookmark_options();
+                        match gdata.bookmark(api, number, restrict, &tags) {
+                            Some(_) => println!("Bookmarked post {}!", number),
+                            None => println!("Failed to bookmark post {}!", number),
+                        }
+                    }
+                    "u" => match gdata.unbookmark(api, number) {
+                        Some(_) => println!("Removed post {} from bookmarks!", number),
+                        None => println!("Failed to unbookmark post {}!", number),
+                    },
+                    _ => println!("Invalid command! Press h to show help"),
+                }
+            }
+            _ => println!("Invalid command! Press h to show help"),
+        }
+    }
+}
+
+pub fn image_prompt(api: &Api, image: &mut data::Image) {
+    loop {
+        let command = match input_or_reload("Enter an image view command: ") {
+            Some(command) => command,
+            None => {
+                image.display_initial();
+                printer::print_config_status(&config::config_errors());
+                continue;
+            }
+        };
+        match command.as_str() {
+            "n" => image.next_image(),
+            "p" => image.previous_image(),
+            "d" => image.download_image(),
+            "a" => image.download_all_pages(),
+            "o" => image.open_image(),
+            "v" => image.open_in_viewer(),
+            "f" => image.show_full_res(),
+            "r" => image.view_related(api),
+            "s" => {
+                let (restrict, tags) = ask_bookmark_options();
+                if image.bookmark(api, restrict, &tags).is_none() {
+                    println!("Failed to bookmark the post!");
+                }
+                println!("{}", image.info_line());
+            }
+            "u" => {
+                if image.unbookmark(api).is_none() {
+                    println!("Failed to unbookmark the post!");
+                }
+                println!("{}", image.info_line());
+            }
+            "F" => {
+                if image.follow(api, ask_follow_restrict()).is_some() {
+                    println!("Followed the artist!");
+                } else {
+                    println!("Failed to follow the artist!");
+                }
+            }
+            "U" => {
+                if image.unfollow(api).is_some() {
+                    println!("Unfollowed the artist!");
+                } else {
+                    println!("Failed to unfollow the artist!");
+                }
+            }
+            "h" => printer::image_help(),
+            "m" => {
+                screens::show_man_loop(Page::Image);
+                image.display_initial();
+            }
+            "q" => ask_quit(),
+            "b" => return,
+            number if pure::str_is_digit(number) && !number.is_empty() => {
+                image.jump_to_image(number.parse().unwrap())
+            }
+            _ => println!("Invalid command! Press h to show help"),
+        }
+    }
+}
+
+/// How much each + or - zooms in or out, and the furthest zoom allowed
+const ZOOM_STEP: f64 = 1.5;
+const MAX_ZOOM: f64 = 16.0;
+
+/// Every key in a command is applied in turn, so `lll` pans three times
+pub fn full_res_prompt(original: &DynamicImage) {
+    let dimensions = original.dimensions();
+    let fit = (dimensions.0 as f64 / 2.0, dimensions.1 as f64 / 2.0);
+    let (mut zoom, mut center) = (1.0, fit);
+    let mut reloaded = false;
+    loop {
+        lscat::clear();
+        lscat::show_zoomed(original, zoom, center, lscat::CENTER_TEXT_ROWS);
+        println!("Zoom: {:.0}%; press ? for help", zoom * 100.0);
+        if reloaded {
+            printer::print_config_status(&config::config_errors());
+        }
+        let command = input_or_reload("Enter a full res command: ");
+        reloaded = command.is_none();
+        let command = command.unwrap_or_default();
+        for key in command.chars() {
+            match key {
+                '+' | '=' => zoom = (zoom * ZOOM_STEP).min(MAX_ZOOM),
+                '-' => zoom = (zoom / ZOOM_STEP).max(1.0),
+                '0' => {
+                    zoom = 1.0;
+                    center = fit;
+                }
+                'h' => center = pure::pan(center, dimensions, zoom, (-1, 0)),
+                'j' => center = pure::pan(center, dimensions, zoom, (0, 1)),
+                'k' => center = pure::pan(center, dimensions, zoom, (0, -1)),
+                'l' => center = pure::pan(center, dimensions, zoom, (1, 0)),
+                '?' => {
+                    printer::full_res_help();
+                    input("Press enter to continue");
+                }
+                'm' => screens::show_man_loop(Page::FullRes),
+                'q' => ask_quit(),
+                'b' => return,
+                _ => {}
+            }
+        }
+        // Zooming out keeps the old center, which may now be too close to an edge
+        center = pure::pan(center, dimensions, zoom, (0, 0));
+    }
+}
+
+fn valid_user(udata: &data::UserData, number: i32) -> Option<i32> {
+    if (number as usize) < udata.names().len() {
+        Some(number)
+    } else {
+        None
+    }
+}
+
+pub fn user_prompt(api: &Api, udata: &mut data::UserData) {
+    udata.show_page();
+    loop {
+        let command = match input_or_reload("Enter a user view command: ") {
+            Some(command) => command,
+            None => {
+                udata.show_page();
+                printer::print_config_status(&config::config_errors());
+                continue;
+            }
+        };
+        let keyseqs = keyseqs(&command);
+        let keyseqs: Vec<&str> = keyseqs.iter().map(|s| s.as_str()).collect();
+
+        match keyseqs.as_slice() {
+            ["n"] => udata.next_page(api),
+            ["p"] => udata.previous_page(api),
+            ["r"] => udata.reload(),
+            ["h"] => printer::user_help(),
+            ["m"] => {
+                screens::show_man_loop(Page::User);
+                udata.show_page();
+            }
+            ["q"] => ask_quit(),
+            ["b"] => return,
+            [_, _] if pure::all_isdigit(keyseqs.clone()) => {
+                match valid_user(udata, pure::concat_seq_to_int(keyseqs.clone(), 0)) {
+                    Some(number) => udata.view_artist(api, number),
+                    None => println!("Invalid number!"),
+                }
+            }
+            [letter @ "f", _, _] | [letter @ "u", _, _]
+                if pure::all_isdigit(keyseqs[1..].to_vec()) =>
+            {
+                let number = match valid_user(udata, pure::concat_seq_to_int(keyseqs.clone(), 1)) {
+                    Some(number) => number,
+                    None => {
+                        println!("Invalid number!");
+                        continue;
+                    }
+                };
+                let name = udata.names()[number as usize].clone();
+                let result = if *letter == "f" {
+                    udata.follow(api, number, ask_follow_restrict())
+                } else {
+                    udata.unfollow(api, number)
+                };
+                match (result, *letter) {
+                    (Some(_), "f") => println!("Followed {}!", name),
+                    (Some(_), _) => println!("Unfollowed {}!", name),
+                    (None, _) => println!("Failed to update following for {}!", name),
+                }
+            }
+            _ => println!("Invalid command! Press h to show help"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyseqs() {
+        assert_eq!(keyseqs("i12"), vec!["i", "1", "2"]);
+        assert_eq!(keyseqs(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_post_number() {
+        assert_eq!(post_number(vec!["i", "1", "2"]), Some(12));
+        assert_eq!(post_number(vec!["O", "0", "7"]), Some(7));
+        assert_eq!(post_number(vec!["o", "x", "7"]), None);
+        assert_eq!(post_number(vec!["i", "1"]), None);
+    }
+}
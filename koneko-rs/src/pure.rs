@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::convert::TryInto;
 
 use regex::Regex;
@@ -12,8 +12,11 @@ pub fn split_backslash_last(s: &str) -> &str {
     s.split('/').last().unwrap()
 }
 
-pub fn generate_filepath(filename: &str) -> PathBuf {
-    dirs::home_dir().unwrap().join("Downloads").join(filename)
+pub fn expand_home(path: &Path) -> PathBuf {
+    match path.strip_prefix("~") {
+        Ok(rest) => dirs::home_dir().unwrap().join(rest),
+        Err(_) => path.to_path_buf(),
+    }
 }
 
 pub fn prefix_filename(oldname_with_ext: &str, newname: &str, number: i32) -> String {
@@ -140,11 +143,87 @@ pub fn newnames_with_ext(
     result
 }
 
-pub fn full_image_details(url: &str, png: bool) -> (String, &str, PathBuf) {
+pub fn full_image_details(url: &str, png: bool) -> (String, String) {
     let fullurl = change_url_to_full(url, png);
-    let filename = split_backslash_last(url);
-    let filepath = generate_filepath(filename);
-    (fullurl, filename, filepath)
+    let filename = split_backslash_last(&fullurl).to_string();
+    (fullurl, filename)
+}
+
+/// The placeholders a `download_template` can have, each written in braces
+pub const TEMPLATE_FIELDS: [&str; 7] = [
+    "artist_name",
+    "artist_id",
+    "date",
+    "title",
+    "id",
+    "page",
+    "ext",
+];
+
+/// A field of a template is cut to this many bytes, so that even with a long
+/// Japanese title, at three bytes a character, a file name stays under 255 bytes
+const MAX_FIELD_BYTES: usize = 80;
+
+/// Makes a title or name safe to be part of a file name: characters that
+/// are not allowed in paths on some system become `_`, and it cannot be
+/// hidden or refer to a parent directory
+pub fn sanitize_path_component(value: &str) -> String {
+    let cleaned: String = value
+        .chars()
+        .map(|c| {
+            if c.is_control() || "/\\:*?\"<>|".contains(c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    let mut end = cleaned.len().min(MAX_FIELD_BYTES);
+    while !cleaned.is_char_boundary(end) {
+        end -= 1;
+    }
+    let trimmed = cleaned[..end].trim().trim_matches('.').trim();
+    if trimmed.is_empty() {
+        "_".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+/// Placeholders in a template that are not in `TEMPLATE_FIELDS`
+pub fn unknown_placeholders(template: &str) -> Vec<String> {
+    let re = Regex::new(r"\{([^{}]*)\}").unwrap();
+    re.captures_iter(template)
+        .map(|cap| cap[1].to_string())
+        .filter(|name| !TEMPLATE_FIELDS.contains(&name.as_str()))
+        .collect()
+}
+
+/// Where a download goes, with every `{field}` in the template replaced by its
+/// sanitized value. Slashes in the template itself make directories. Values are
+/// not searched for placeholders, so a title with `{id}` in it stays as it is
+pub fn generate_filepath(template: &str, fields: &[(&str, String)]) -> PathBuf {
+    let re = Regex::new(r"\{([^{}]*)\}").unwrap();
+    let path = re.replace_all(template, |cap: &regex::Captures| {
+        match fields.iter().find(|(name, _)| *name == &cap[1]) {
+            Some((_, value)) => sanitize_path_component(value),
+            None => cap[0].to_string(),
+        }
+    });
+    expand_home(Path::new(path.as_ref()))
+}
+
+/// The directory every download from a template goes under: the part of it
+/// before the first placeholder, or the directory of the file if there is none
+pub fn template_root(template: &str) -> PathBuf {
+    let mut fixed: Vec<&str> = template
+        .split('/')
+        .take_while(|part| !part.contains('{'))
+        .collect();
+    if !template.contains('{') {
+        fixed.pop();
+    }
+    expand_home(Path::new(&fixed.join("/")))
 }
 
 pub fn concat_seq_to_int(keyseqs: Vec<&str>, start: i32) -> i32 {
@@ -180,6 +259,128 @@ pub fn ycoords(term_height: u16, image_height: i32, padding: i32) -> Vec<i32> {
         .collect()
 }
 
+/// The inverse of `utils::find_number_map`: the (x, y) a post number is selected with
+pub fn number_to_coords(number: i32, ncols: i32) -> (i32, i32) {
+    (number.rem_euclid(ncols) + 1, number / ncols + 1)
+}
+
+/// A row of the text-only gallery, with everything the thumbnail grid would show
+pub fn gallery_text_line(
+    number: i32,
+    coords: (i32, i32),
+    post_json: &Value,
+    label: &str,
+) -> String {
+    let mut details = vec![];
+    let pages = post_json["page_count"].as_i64().unwrap_or(1);
+    if pages > 1 {
+        details.push(format!("{} pages", pages));
+    }
+    if let Some(bookmarks) = post_json["total_bookmarks"].as_i64() {
+        details.push(format!("{} bookmarks", bookmarks));
+    }
+    if post_json["is_bookmarked"].as_bool() == Some(true) {
+        details.push("bookmarked".to_string());
+    }
+    let label = if label.is_empty() {
+        String::new()
+    } else {
+        format!("{} ", label)
+    };
+    format!(
+        "{:0>2}  {}{}  {}{} by {} ({})",
+        number,
+        coords.0,
+        coords.1,
+        label,
+        post_json["title"].as_str().unwrap_or(""),
+        post_json["user"]["name"].as_str().unwrap_or(""),
+        details.join(", ")
+    )
+}
+
+/// A row of the text-only user list
+pub fn user_text_line(number: i32, user_preview: &Value) -> String {
+    let illusts = user_preview["illusts"].as_array().map_or(0, |x| x.len());
+    format!(
+        "{:0>2}  {} ({}, {} recent illusts)",
+        number,
+        user_preview["user"]["name"].as_str().unwrap_or(""),
+        user_preview["user"]["id"],
+        illusts
+    )
+}
+
+/// Which pages to show in a strip with room for `slots` of them,
+/// keeping the current page in the middle where possible
+pub fn preview_window(current: usize, total: usize, slots: usize) -> std::ops::Range<usize> {
+    let end = (current.saturating_sub(slots / 2) + slots).min(total);
+    end.saturating_sub(slots)..end
+}
+
+/// The largest size with the same aspect ratio as the image that fits in the box
+pub fn fit_size(image: (u32, u32), bounds: (u32, u32)) -> (u32, u32) {
+    let scale = f64::min(
+        bounds.0 as f64 / image.0.max(1) as f64,
+        bounds.1 as f64 / image.1.max(1) as f64,
+    );
+    (
+        ((image.0 as f64 * scale).round() as u32).clamp(1, bounds.0.max(1)),
+        ((image.1 as f64 * scale).round() as u32).clamp(1, bounds.1.max(1)),
+    )
+}
+
+/// A size in bytes in the largest unit it is at least one of, like `du -h`
+pub fn human_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+/// The part of an image that is visible when zoomed in `zoom` times around
+/// `center`, as (x, y, width, height). The box is kept inside the image
+pub fn zoom_crop(image: (u32, u32), zoom: f64, center: (f64, f64)) -> (u32, u32, u32, u32) {
+    let width = ((image.0 as f64 / zoom).round() as u32).clamp(1, image.0.max(1));
+    let height = ((image.1 as f64 / zoom).round() as u32).clamp(1, image.1.max(1));
+    let x = (center.0 - width as f64 / 2.0)
+        .round()
+        .clamp(0.0, image.0.saturating_sub(width) as f64);
+    let y = (center.1 - height as f64 / 2.0)
+        .round()
+        .clamp(0.0, image.1.saturating_sub(height) as f64);
+    (x as u32, y as u32, width, height)
+}
+
+/// Moves the center of a zoomed view by a quarter of the visible part in
+/// `direction` (-1, 0 or 1 for each axis), stopping at the edges of the image
+pub fn pan(center: (f64, f64), image: (u32, u32), zoom: f64, direction: (i32, i32)) -> (f64, f64) {
+    let visible = (image.0 as f64 / zoom, image.1 as f64 / zoom);
+    let step = |center: f64, size: u32, visible: f64, direction: i32| {
+        let half = (visible / 2.0).min(size as f64 / 2.0);
+        (center + direction as f64 * visible / 4.0).clamp(half, size as f64 - half)
+    };
+    (
+        step(center.0, image.0, visible.0, direction.0),
+        step(center.1, image.1, visible.1, direction.1),
+    )
+}
+
+pub fn grid_position(number: i32, ncols: i32, nrows: i32) -> (usize, usize) {
+    (
+        number.rem_euclid(ncols) as usize,
+        (number / ncols).rem_euclid(nrows) as usize,
+    )
+}
+
 pub fn generate_orders(total_pics: i32, artist_count: i32) -> Vec<i32> {
     let range: Vec<i32> = (0..artist_count).collect();
     let mut i = 0;
@@ -196,6 +397,48 @@ pub fn generate_orders(total_pics: i32, artist_count: i32) -> Vec<i32> {
     order
 }
 
+/// Lays out labels like `printer::print_cols` lays out column numbers,
+/// eating into the next gap when a label is wider than one character
+pub fn labels_line(spacings: &[i32], labels: &[String]) -> String {
+    let mut line = String::new();
+    let mut overflow = 0;
+    for (space, label) in spacings.iter().zip(labels) {
+        line.push_str(&" ".repeat((space - overflow).max(1) as usize));
+        line.push_str(label);
+        overflow = label.chars().count() as i32 - 1;
+    }
+    line
+}
+
+fn preview_user_id(user_preview: &Value) -> String {
+    user_preview["user"]["id"].to_string()
+}
+
+/// Removes a user from a page of `user_previews`, returning whether they were in it
+pub fn remove_user_preview(page: &mut Value, user_id: &str) -> bool {
+    match page["user_previews"].as_array_mut() {
+        Some(previews) => {
+            let before = previews.len();
+            previews.retain(|x| preview_user_id(x) != user_id);
+            previews.len() != before
+        }
+        None => false,
+    }
+}
+
+pub fn has_user_preview(page: &Value, user_id: &str) -> bool {
+    page["user_previews"]
+        .as_array()
+        .is_some_and(|previews| previews.iter().any(|x| preview_user_id(x) == user_id))
+}
+
+/// Newly followed users are shown first, like in the api
+pub fn insert_user_preview(page: &mut Value, user_preview: Value) {
+    if let Some(previews) = page["user_previews"].as_array_mut() {
+        previews.insert(0, user_preview);
+    }
+}
+
 pub fn line_width(spacings: Vec<i32>, ncols: i32) -> i32 {
     spacings.iter().sum::<i32>() + ncols
 }
@@ -226,12 +469,97 @@ mod tests {
 
     #[test]
     fn test_generate_filepath() {
+        let fields = vec![
+            ("id", "78823485".to_string()),
+            ("page", "0".to_string()),
+            ("ext", "jpg".to_string()),
+        ];
         assert_eq!(
-            generate_filepath(&"78823485_p0.jpg"),
+            generate_filepath(&"~/Downloads/{id}_p{page}.{ext}", &fields),
             dirs::home_dir().unwrap().join("Downloads/78823485_p0.jpg")
         );
     }
 
+    #[test]
+    fn test_sanitize_path_component() {
+        assert_eq!(
+            sanitize_path_component("Fate/Grand Order"),
+            "Fate_Grand Order"
+        );
+        assert_eq!(
+            sanitize_path_component("what?: <a|b> \"c\"\\*"),
+            "what__ _a_b_ _c___"
+        );
+        assert_eq!(sanitize_path_component("夏の日\n"), "夏の日_");
+        assert_eq!(sanitize_path_component(".."), "_");
+        assert_eq!(sanitize_path_component(" .hidden. "), "hidden");
+        assert_eq!(sanitize_path_component(""), "_");
+
+        // Cut on a character boundary, not in the middle of one
+        let long = "あ".repeat(40);
+        assert_eq!(sanitize_path_component(&long), "あ".repeat(26));
+    }
+
+    #[test]
+    fn test_unknown_placeholders() {
+        assert!(
+            unknown_placeholders("~/Pictures/{artist_name}_{artist_id}/{id}_p{page}.{ext}")
+                .is_empty()
+        );
+        assert_eq!(unknown_placeholders("{id}_{name}.{ext}"), vec!["name"]);
+    }
+
+    #[test]
+    fn test_template_root() {
+        assert_eq!(
+            template_root("/pics/{artist_name}_{artist_id}/{id}_p{page}.{ext}"),
+            PathBuf::from("/pics")
+        );
+        assert_eq!(
+            template_root("/pics/koneko/img_{id}.{ext}"),
+            PathBuf::from("/pics/koneko")
+        );
+        assert_eq!(template_root("/pics/image.png"), PathBuf::from("/pics"));
+        assert_eq!(template_root("{id}.{ext}"), PathBuf::from(""));
+    }
+
+    #[test]
+    fn test_generate_filepath_fields() {
+        let fields = vec![
+            ("artist_name", "ファースト/ID".to_string()),
+            ("artist_id", "2232374".to_string()),
+            ("date", "2019-09-09".to_string()),
+            ("title", "Hello: world".to_string()),
+            ("id", "76695217".to_string()),
+            ("page", "0".to_string()),
+            ("ext", "png".to_string()),
+        ];
+        assert_eq!(
+            generate_filepath(
+                "/pics/{artist_name}_{artist_id}/{date}_{title}_{id}_p{page}.{ext}",
+                &fields
+            ),
+            PathBuf::from("/pics/ファースト_ID_2232374/2019-09-09_Hello_ world_76695217_p0.png")
+        );
+        let braces = vec![
+            ("title", "{id} and {page}".to_string()),
+            ("id", "1".to_string()),
+        ];
+        assert_eq!(
+            generate_filepath("/pics/{title}_{id}_{unknown}", &braces),
+            PathBuf::from("/pics/{id} and {page}_1_{unknown}")
+        );
+    }
+
+    #[test]
+    fn test_expand_home() {
+        assert_eq!(
+            expand_home(Path::new("~/.local/share/koneko/cache")),
+            dirs::home_dir().unwrap().join(".local/share/koneko/cache")
+        );
+        assert_eq!(expand_home(Path::new("/tmp/koneko")), Path::new("/tmp/koneko"));
+    }
+
     #[test]
     fn test_prefix_filename() {
         assert_eq!(prefix_filename(&"old.jpg", &"new", 2), "002_new.jpg");
@@ -285,6 +613,13 @@ mod tests {
         assert_eq!(change_url_to_full("https://i.pximg.net/c/540x540_70/img-master/img/2019/09/09/04/32/38/76695217_p0_master1200.jpg", false), "https://i.pximg.net/c/540x540_70/img-master/img/2019/09/09/04/32/38/76695217_p0.jpg");
     }
 
+    #[test]
+    fn test_full_image_details() {
+        let (url, filename) = full_image_details("https://i.pximg.net/c/600x1200_90_webp/img-master/img/2019/09/09/04/32/38/76695217_p0_master1200.jpg", true);
+        assert_eq!(url, "https://i.pximg.net/img-original/img/2019/09/09/04/32/38/76695217_p0.png");
+        assert_eq!(filename, "76695217_p0.png");
+    }
+
     #[test]
     fn test_process_user_url() {
         assert_eq!(
@@ -338,6 +673,103 @@ mod tests {
         assert_eq!(ycoords(20, 8, 1), vec![0, 9]);
     }
 
+    #[test]
+    fn test_number_to_coords() {
+        assert_eq!(number_to_coords(0, 5), (1, 1));
+        assert_eq!(number_to_coords(7, 5), (3, 2));
+        assert_eq!(number_to_coords(29, 5), (5, 6));
+    }
+
+    #[test]
+    fn test_text_lines() {
+        let file = fs::File::open("testing/files/mode1.json").unwrap();
+        let gallery: Value = serde_json::from_reader(file).unwrap();
+        let post = &gallery["illusts"][0];
+        let line = gallery_text_line(0, (1, 1), post, "");
+        assert!(line.starts_with(&format!("00  11  {} by ", post["title"].as_str().unwrap())));
+        assert!(line.ends_with(&format!("({} bookmarks)", post["total_bookmarks"])));
+        assert!(gallery_text_line(7, (3, 2), post, "#8").starts_with("07  32  #8 "));
+
+        let file = fs::File::open("testing/files/mode3.json").unwrap();
+        let users: Value = serde_json::from_reader(file).unwrap();
+        assert_eq!(
+            user_text_line(0, &users["user_previews"][0]),
+            "00  畳と桧 (219621, 3 recent illusts)"
+        );
+    }
+
+    #[test]
+    fn test_preview_window() {
+        assert_eq!(preview_window(0, 8, 5), 0..5);
+        assert_eq!(preview_window(4, 8, 5), 2..7);
+        assert_eq!(preview_window(7, 8, 5), 3..8);
+        assert_eq!(preview_window(1, 3, 5), 0..3);
+    }
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(200 * 1024 * 1024), "200.0 MiB");
+    }
+
+    #[test]
+    fn test_fit_size() {
+        assert_eq!(fit_size((540, 540), (180, 160)), (160, 160));
+        assert_eq!(fit_size((600, 1200), (1000, 600)), (300, 600));
+        assert_eq!(fit_size((10, 5), (40, 40)), (40, 20));
+        assert_eq!(fit_size((1000, 1), (10, 10)), (10, 1));
+    }
+
+    #[test]
+    fn test_zoom_crop() {
+        assert_eq!(
+            zoom_crop((1000, 800), 1.0, (500.0, 400.0)),
+            (0, 0, 1000, 800)
+        );
+        assert_eq!(
+            zoom_crop((1000, 800), 2.0, (500.0, 400.0)),
+            (250, 200, 500, 400)
+        );
+        assert_eq!(
+            zoom_crop((1000, 800), 2.0, (0.0, 800.0)),
+            (0, 400, 500, 400)
+        );
+        assert_eq!(
+            zoom_crop((1000, 800), 4.0, (900.0, 100.0)),
+            (750, 0, 250, 200)
+        );
+    }
+
+    #[test]
+    fn test_pan() {
+        assert_eq!(
+            pan((500.0, 400.0), (1000, 800), 2.0, (1, 0)),
+            (625.0, 400.0)
+        );
+        assert_eq!(
+            pan((500.0, 400.0), (1000, 800), 2.0, (0, -1)),
+            (500.0, 300.0)
+        );
+        assert_eq!(
+            pan((700.0, 200.0), (1000, 800), 2.0, (1, -1)),
+            (750.0, 200.0)
+        );
+        // Nothing to pan when the whole image is visible
+        assert_eq!(
+            pan((500.0, 400.0), (1000, 800), 1.0, (1, 1)),
+            (500.0, 400.0)
+        );
+    }
+
+    #[test]
+    fn test_grid_position() {
+        assert_eq!(grid_position(0, 5, 2), (0, 0));
+        assert_eq!(grid_position(7, 5, 2), (2, 1));
+        assert_eq!(grid_position(12, 5, 2), (2, 0));
+    }
+
     #[test]
     fn test_generate_orders() {
         assert_eq!(
@@ -353,6 +785,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_labels_line() {
+        let labels: Vec<String> = vec!["1".to_string(), "#10".to_string(), "#11".to_string()];
+        assert_eq!(
+            labels_line(&[2, 3, 4], &labels),
+            "  1   #10  #11"
+        );
+        assert_eq!(labels_line(&[9, 17], &labels[..1]), format!("{}1", " ".repeat(9)));
+        assert_eq!(labels_line(&[1, 1, 1], &labels[1..]), " #10 #11");
+    }
+
+    #[test]
+    fn test_user_previews() {
+        let file = fs::File::open("testing/files/mode3.json").unwrap();
+        let mut page: Value = serde_json::from_reader(file).unwrap();
+        let first = page["user_previews"][0].clone();
+
+        assert!(has_user_preview(&page, "219621"));
+        assert!(remove_user_preview(&mut page, "219621"));
+        assert!(!remove_user_preview(&mut page, "219621"));
+        assert!(!has_user_preview(&page, "219621"));
+        assert_eq!(page["user_previews"].as_array().unwrap().len(), 29);
+        assert_eq!(page["user_previews"][0]["user"]["id"], 1510169);
+
+        insert_user_preview(&mut page, first);
+        assert_eq!(page["user_previews"].as_array().unwrap().len(), 30);
+        assert_eq!(page["user_previews"][0]["user"]["id"], 219621);
+    }
+
     #[test]
     fn test_all_isdigit() {
         assert_eq!(all_isdigit(vec!["1", "4"]), true);
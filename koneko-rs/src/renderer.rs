@@ -0,0 +1,495 @@
+//! Backends that draw images into the terminal
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufReader, Cursor};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use image::{DynamicImage, GenericImageView, ImageFormat, ImageReader, Rgba, RgbaImage};
+use image::imageops::FilterType;
+
+use crate::config;
+use crate::printer;
+use crate::pure;
+use crate::system::system;
+use crate::thumbnail;
+use crate::utils;
+
+/// Used when the terminal does not report its size in pixels
+const FALLBACK_CELL_SIZE: (u32, u32) = (10, 20);
+/// The kitty graphics protocol takes at most this many bytes of data per escape code
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+pub trait Renderer {
+    /// Draws an image scaled to fit in `size` (columns, rows) cells,
+    /// with its top left corner at cell (x, y)
+    fn show(&self, path: &Path, size: (i32, i32), x: i32, y: i32) {
+        if let Some(image) = open_image(path) {
+            self.show_image(&image, size, x, y)
+        }
+    }
+
+    /// Like `show`, for an image that has already been decoded
+    fn show_image(&self, image: &DynamicImage, size: (i32, i32), x: i32, y: i32);
+
+    /// The box in pixels that an image drawn in `size` cells is scaled to fit
+    fn pixel_bounds(&self, size: (i32, i32)) -> (u32, u32);
+
+    /// Like `show`, but draws the cached thumbnail of the image for this size,
+    /// making it first if needed. See `thumbnail::cached`
+    fn show_thumbnail(&self, path: &Path, size: (i32, i32), x: i32, y: i32) {
+        match thumbnail::cached(path, self.pixel_bounds(size)) {
+            Some(thumbnail) => self.show(&thumbnail, size, x, y),
+            None => self.show(path, size, x, y),
+        }
+    }
+
+    /// Clears the screen, along with every image on it
+    fn clear(&self) {
+        system().clear();
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    Kitty,
+    Sixel,
+    Iterm2,
+    Blocks,
+}
+
+impl Backend {
+    pub fn from_name(name: &str) -> Option<Backend> {
+        match name {
+            "kitty" => Some(Backend::Kitty),
+            "sixel" => Some(Backend::Sixel),
+            "iterm2" => Some(Backend::Iterm2),
+            "blocks" => Some(Backend::Blocks),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Backend::Kitty => "kitty",
+            Backend::Sixel => "sixel",
+            Backend::Iterm2 => "iterm2",
+            Backend::Blocks => "blocks",
+        }
+    }
+
+    pub fn from_env() -> Backend {
+        Backend::detect(
+            &env::var("TERM").unwrap_or_default(),
+            &env::var("TERM_PROGRAM").unwrap_or_default(),
+            env::var_os("KITTY_WINDOW_ID").is_some(),
+        )
+    }
+
+    /// Guesses what the terminal supports from `$TERM` and `$TERM_PROGRAM`
+    pub fn detect(term: &str, term_program: &str, in_kitty: bool) -> Backend {
+        if in_kitty || term.contains("kitty") || term.contains("ghostty") {
+            Backend::Kitty
+        } else if term_program == "iTerm.app" || term_program == "WezTerm" {
+            Backend::Iterm2
+        } else if term.starts_with("foot") || term.contains("mlterm") || term_program == "mintty" {
+            Backend::Sixel
+        } else {
+            Backend::Blocks
+        }
+    }
+
+    pub fn renderer(self) -> Box<dyn Renderer> {
+        let cell_size = utils::cell_size().unwrap_or(FALLBACK_CELL_SIZE);
+        match self {
+            Backend::Kitty => Box::new(Kitty { cell_size }),
+            Backend::Sixel => Box::new(Sixel { cell_size }),
+            Backend::Iterm2 => Box::new(Iterm2 { cell_size }),
+            Backend::Blocks => Box::new(Blocks),
+        }
+    }
+}
+
+/// The backend set in the config, or the detected one if it is `auto`
+pub fn backend() -> Backend {
+    Backend::from_name(&config::renderer_config()).unwrap_or_else(Backend::from_env)
+}
+
+pub fn renderer() -> Box<dyn Renderer> {
+    backend().renderer()
+}
+
+/// pixiv serves WebP under names ending in `.jpg`, so the format is
+/// guessed from the file's contents rather than from its extension
+fn image_reader(path: &Path) -> Option<ImageReader<BufReader<File>>> {
+    ImageReader::open(path).ok()?.with_guessed_format().ok()
+}
+
+pub fn open_image(path: &Path) -> Option<DynamicImage> {
+    image_reader(path)?.decode().ok()
+}
+
+pub fn image_dimensions(path: &Path) -> Option<(u32, u32)> {
+    image_reader(path)?.into_dimensions().ok()
+}
+
+pub fn image_format(path: &Path) -> Option<ImageFormat> {
+    image_reader(path)?.format()
+}
+
+/// Scales an image to fit in a box of pixels, unless it already does
+fn fitted_image(image: &DynamicImage, bounds: (u32, u32)) -> DynamicImage {
+    let (width, height) = pure::fit_size(image.dimensions(), bounds);
+    if (width, height) == image.dimensions() {
+        return image.clone();
+    }
+    image.resize_exact(width, height, FilterType::Triangle)
+}
+
+/// True if the file is a png that already fits the box, so it can be sent as it is
+fn is_fitted_png(path: &Path, bounds: (u32, u32)) -> bool {
+    image_format(path) == Some(ImageFormat::Png)
+        && image_dimensions(path)
+            .is_some_and(|dimensions| pure::fit_size(dimensions, bounds) == dimensions)
+}
+
+fn encode_png(image: &DynamicImage) -> Option<Vec<u8>> {
+    let mut png = Cursor::new(vec![]);
+    image.write_to(&mut png, ImageFormat::Png).ok()?;
+    Some(png.into_inner())
+}
+
+fn cells_in_pixels(size: (i32, i32), cell_size: (u32, u32)) -> (u32, u32) {
+    (
+        size.0.max(1) as u32 * cell_size.0,
+        size.1.max(1) as u32 * cell_size.1,
+    )
+}
+
+/// The kitty graphics protocol, also understood by ghostty and konsole
+pub struct Kitty {
+    cell_size: (u32, u32),
+}
+
+impl Renderer for Kitty {
+    /// Cached thumbnails are sent without decoding them
+    fn show(&self, path: &Path, size: (i32, i32), x: i32, y: i32) {
+        if !is_fitted_png(path, self.pixel_bounds(size)) {
+            if let Some(image) = open_image(path) {
+                self.show_image(&image, size, x, y);
+            }
+            return;
+        }
+        if let Ok(png) = fs::read(path) {
+            printer::move_cursor_xy(x, y);
+            printer::write(&kitty_escape(&png));
+        }
+    }
+
+    fn show_image(&self, image: &DynamicImage, size: (i32, i32), x: i32, y: i32) {
+        let image = fitted_image(image, self.pixel_bounds(size));
+        if let Some(png) = encode_png(&image) {
+            printer::move_cursor_xy(x, y);
+            printer::write(&kitty_escape(&png));
+        }
+    }
+
+    fn pixel_bounds(&self, size: (i32, i32)) -> (u32, u32) {
+        cells_in_pixels(size, self.cell_size)
+    }
+
+    fn clear(&self) {
+        printer::write("\x1b_Ga=d,q=2\x1b\\");
+        system().clear();
+    }
+}
+
+/// Sends a png in chunks, without moving the cursor
+fn kitty_escape(png: &[u8]) -> String {
+    let data = BASE64.encode(png);
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK_SIZE).collect();
+    let mut result = String::new();
+    for (idx, chunk) in chunks.iter().enumerate() {
+        let more = (idx + 1 < chunks.len()) as i32;
+        let control = if idx == 0 {
+            format!("a=T,f=100,t=d,C=1,q=2,m={}", more)
+        } else {
+            format!("m={}", more)
+        };
+        result.push_str(&format!(
+            "\x1b_G{};{}\x1b\\",
+            control,
+            std::str::from_utf8(chunk).unwrap()
+        ));
+    }
+    result
+}
+
+/// DEC sixel graphics, as in foot, mlterm and xterm with `-ti vt340`
+pub struct Sixel {
+    cell_size: (u32, u32),
+}
+
+impl Renderer for Sixel {
+    fn show_image(&self, image: &DynamicImage, size: (i32, i32), x: i32, y: i32) {
+        let image = fitted_image(image, self.pixel_bounds(size));
+        printer::move_cursor_xy(x, y);
+        printer::write(&sixel(&image.to_rgba8()));
+    }
+
+    fn pixel_bounds(&self, size: (i32, i32)) -> (u32, u32) {
+        cells_in_pixels(size, self.cell_size)
+    }
+}
+
+/// Rounds a color to the 6x6x6 color cube, or None if it is transparent
+fn palette_index(pixel: &Rgba<u8>) -> Option<usize> {
+    if pixel[3] < 128 {
+        return None;
+    }
+    let level = |c: u8| (c as usize * 5 + 127) / 255;
+    Some(level(pixel[0]) * 36 + level(pixel[1]) * 6 + level(pixel[2]))
+}
+
+/// Runs of more than three identical sixels are written as `!<count><sixel>`
+fn push_run_length(result: &mut String, sixels: &[u8]) {
+    let mut idx = 0;
+    while idx < sixels.len() {
+        let run = sixels[idx..]
+            .iter()
+            .take_while(|&&x| x == sixels[idx])
+            .count();
+        let sixel = (63 + sixels[idx]) as char;
+        if run > 3 {
+            result.push_str(&format!("!{}{}", run, sixel));
+        } else {
+            result.push_str(&sixel.to_string().repeat(run));
+        }
+        idx += run;
+    }
+}
+
+fn sixel(image: &RgbaImage) -> String {
+    let (width, height) = image.dimensions();
+    let mut result = format!("\x1bP0;1;q\"1;1;{};{}", width, height);
+    for idx in 0..216 {
+        let (r, g, b) = (idx / 36, idx / 6 % 6, idx % 6);
+        result.push_str(&format!("#{};2;{};{};{}", idx, r * 20, g * 20, b * 20));
+    }
+    for band in (0..height).step_by(6) {
+        // Each color used in the band gets its own row of sixels
+        let mut rows: BTreeMap<usize, Vec<u8>> = BTreeMap::new();
+        for dy in 0..6.min(height - band) {
+            for x in 0..width {
+                if let Some(color) = palette_index(image.get_pixel(x, band + dy)) {
+                    rows.entry(color).or_insert_with(|| vec![0; width as usize])[x as usize] |=
+                        1 << dy;
+                }
+            }
+        }
+        for (idx, (color, sixels)) in rows.iter().enumerate() {
+            if idx != 0 {
+                result.push('$');
+            }
+            result.push_str(&format!("#{}", color));
+            push_run_length(&mut result, sixels);
+        }
+        result.push('-');
+    }
+    result.push_str("\x1b\\");
+    result
+}
+
+/// iTerm2's inline images, also understood by WezTerm. The terminal does the scaling,
+/// but WebP is not among the formats it can decode
+pub struct Iterm2 {
+    cell_size: (u32, u32),
+}
+
+impl Renderer for Iterm2 {
+    /// Pngs and jpgs are sent as they are, without decoding them
+    fn show(&self, path: &Path, size: (i32, i32), x: i32, y: i32) {
+        match image_format(path) {
+            Some(ImageFormat::Png) | Some(ImageFormat::Jpeg) => {
+                if let Ok(bytes) = fs::read(path) {
+                    printer::move_cursor_xy(x, y);
+                    printer::write(&iterm2_escape(&bytes, size));
+                }
+            }
+            _ => {
+                if let Some(image) = open_image(path) {
+                    self.show_image(&image, size, x, y);
+                }
+            }
+        }
+    }
+
+    fn show_image(&self, image: &DynamicImage, size: (i32, i32), x: i32, y: i32) {
+        if let Some(png) = encode_png(image) {
+            printer::move_cursor_xy(x, y);
+            printer::write(&iterm2_escape(&png, size));
+        }
+    }
+
+    /// Only so that thumbnails are not much larger than what is shown
+    fn pixel_bounds(&self, size: (i32, i32)) -> (u32, u32) {
+        cells_in_pixels(size, self.cell_size)
+    }
+}
+
+fn iterm2_escape(bytes: &[u8], size: (i32, i32)) -> String {
+    format!(
+        "\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=1:{}\x07",
+        bytes.len(),
+        size.0,
+        size.1,
+        BASE64.encode(bytes)
+    )
+}
+
+/// Upper half blocks with truecolor, so each cell shows two pixels.
+/// Works in any terminal with truecolor support
+pub struct Blocks;
+
+impl Renderer for Blocks {
+    fn show_image(&self, image: &DynamicImage, size: (i32, i32), x: i32, y: i32) {
+        let image = fitted_image(image, self.pixel_bounds(size));
+        for (row, line) in half_blocks(&image.to_rgba8()).iter().enumerate() {
+            printer::move_cursor_xy(x, y + row as i32);
+            printer::write(line);
+        }
+    }
+
+    fn pixel_bounds(&self, size: (i32, i32)) -> (u32, u32) {
+        (size.0.max(1) as u32, size.1.max(1) as u32 * 2)
+    }
+}
+
+fn half_blocks(image: &RgbaImage) -> Vec<String> {
+    let (width, height) = image.dimensions();
+    (0..height)
+        .step_by(2)
+        .map(|y| {
+            let mut line = String::new();
+            for x in 0..width {
+                let top = image.get_pixel(x, y);
+                line.push_str(&format!("\x1b[38;2;{};{};{}m", top[0], top[1], top[2]));
+                if y + 1 < height {
+                    let bottom = image.get_pixel(x, y + 1);
+                    line.push_str(&format!(
+                        "\x1b[48;2;{};{};{}m",
+                        bottom[0], bottom[1], bottom[2]
+                    ));
+                } else {
+                    line.push_str("\x1b[49m");
+                }
+                line.push('▀');
+            }
+            line.push_str("\x1b[0m");
+            line
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backend_from_name() {
+        assert_eq!(Backend::from_name("sixel"), Some(Backend::Sixel));
+        assert_eq!(Backend::from_name("auto"), None);
+    }
+
+    #[test]
+    fn test_backend_detect() {
+        assert_eq!(Backend::detect("xterm-256color", "", true), Backend::Kitty);
+        assert_eq!(Backend::detect("xterm-kitty", "", false), Backend::Kitty);
+        assert_eq!(
+            Backend::detect("xterm-256color", "WezTerm", false),
+            Backend::Iterm2
+        );
+        assert_eq!(Backend::detect("foot", "", false), Backend::Sixel);
+        assert_eq!(Backend::detect("xterm", "", false), Backend::Blocks);
+    }
+
+    #[test]
+    fn test_half_blocks() {
+        let image = RgbaImage::from_fn(2, 3, |x, y| Rgba([x as u8, y as u8, 9, 255]));
+        assert_eq!(
+            half_blocks(&image),
+            [
+                "\x1b[38;2;0;0;9m\x1b[48;2;0;1;9m▀\x1b[38;2;1;0;9m\x1b[48;2;1;1;9m▀\x1b[0m",
+                "\x1b[38;2;0;2;9m\x1b[49m▀\x1b[38;2;1;2;9m\x1b[49m▀\x1b[0m",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sixel() {
+        let image = RgbaImage::from_fn(5, 7, |x, _| {
+            if x == 0 {
+                Rgba([0, 0, 0, 0])
+            } else {
+                Rgba([255, 0, 0, 255])
+            }
+        });
+        let encoded = sixel(&image);
+        assert!(encoded.starts_with("\x1bP0;1;q\"1;1;5;7#0;2;0;0;0"));
+        // Red is #180; the first band is six rows high, the second is one
+        assert!(encoded.ends_with("#180?!4~-#180?!4@-\x1b\\"));
+    }
+
+    #[test]
+    fn test_push_run_length() {
+        let mut result = String::new();
+        push_run_length(&mut result, &[0, 0, 0, 1, 1, 1, 1, 63]);
+        assert_eq!(result, "???!4@~");
+    }
+
+    #[test]
+    fn test_kitty_escape() {
+        let escape = kitty_escape(&[0; 4000]);
+        assert!(escape.starts_with("\x1b_Ga=T,f=100,t=d,C=1,q=2,m=1;AAAA"));
+        assert_eq!(escape.matches("\x1b_G").count(), 2);
+        assert!(escape.contains("\x1b\\\x1b_Gm=0;"));
+    }
+
+    #[test]
+    fn test_iterm2_escape() {
+        assert_eq!(
+            iterm2_escape(b"png", (18, 8)),
+            "\x1b]1337;File=inline=1;size=3;width=18;height=8;preserveAspectRatio=1:cG5n\x07"
+        );
+    }
+
+    #[test]
+    fn test_fitted_image() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(40, 20));
+        assert_eq!(fitted_image(&image, (20, 20)).dimensions(), (20, 10));
+        assert_eq!(fitted_image(&image, (80, 20)).dimensions(), (40, 20));
+
+        // Named like pixiv's thumbnails, which are not what their extension says
+        let path = std::env::temp_dir().join("koneko-test-fitted.jpg");
+        image.save_with_format(&path, ImageFormat::Png).unwrap();
+        assert!(is_fitted_png(&path, (80, 20)));
+        assert!(!is_fitted_png(&path, (20, 20)));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_open_webp() {
+        let path = std::env::temp_dir().join("koneko-test-webp_p0_square1200.jpg");
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(6, 4, Rgba([255, 0, 0, 255])));
+        image.save_with_format(&path, ImageFormat::WebP).unwrap();
+        assert_eq!(image_format(&path), Some(ImageFormat::WebP));
+        assert_eq!(image_dimensions(&path), Some((6, 4)));
+        let decoded = open_image(&path).unwrap().to_rgba8();
+        assert_eq!(decoded.get_pixel(5, 3), &Rgba([255, 0, 0, 255]));
+        assert!(image::open(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
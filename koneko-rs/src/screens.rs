@@ -1,7 +1,43 @@
-use std::process::Command;
 use std::io;
+use std::path::Path;
+use std::sync::Mutex;
 
 use crate::{__version__, KONEKODIR};
+use crate::colors::{self, n, q};
+use crate::config;
+use crate::lscat;
+use crate::manual::{self, Page};
+use crate::printer;
+use crate::prompt;
+use crate::pure;
+use crate::system::system;
+use crate::utils;
+
+/// The size of the cache, measured once since walking all of it gets slow as
+/// it grows. None until then, or after the cache is cleared
+static CACHE_SIZE: Mutex<Option<u64>> = Mutex::new(None);
+
+fn cache_size() -> Option<u64> {
+    let mut size = CACHE_SIZE.lock().unwrap();
+    if size.is_none() {
+        *size = system().disk_usage(&pure::expand_home(Path::new(KONEKODIR)));
+    }
+    *size
+}
+
+/// Columns taken up by text, where CJK characters are two columns wide
+fn text_width(line: &str) -> i32 {
+    line.chars().map(|c| if c.is_ascii() { 1 } else { 2 }).sum()
+}
+
+/// Clears the screen, and prints the messages with one of the bundled pics to their right
+fn print_beside_pic(messages: &[&str], pic: &str) {
+    lscat::clear();
+    messages.iter().for_each(|m| println!("{}", m));
+    let lines: Vec<&str> = messages.iter().flat_map(|m| m.split('\n')).collect();
+    let columns = lines.iter().map(|line| text_width(line)).max().unwrap_or(0);
+    lscat::show_beside_text(&utils::pics_dir().join(pic), columns, lines.len() as i32);
+}
 
 pub fn begin_prompt(printmessages: bool) -> String {
     let messages = [
@@ -12,6 +48,10 @@ pub fn begin_prompt(printmessages: bool) -> String {
         "3. View following artists",
         "4. Search for artists",
         "5. View illustrations of all following artists",
+        "6. Search for illustrations by tag",
+        "7. View illustration rankings",
+        "8. View recommended illustrations",
+        "9. View your bookmarks",
         "f. Frequent modes and user inputs", "",
         "?. Info",
         "m. Manual",
@@ -19,37 +59,110 @@ pub fn begin_prompt(printmessages: bool) -> String {
         "q. Quit",
     ];
     if printmessages {
-        messages.iter().for_each(|m| println!("{}", m))
+        print_beside_pic(&messages, utils::WELCOME_PIC);
     };
-    // TODO: show pixcat image
-    let size = Command::new("du").args(&["-hs", "--apparent-size", &KONEKODIR]).output().unwrap();
-    // FIXME: KONEKODIR not found
-    //du -hs --apparent-size {KONEKODIR} | cut -f1
-    //println!("Current cache size = {}", size.stdout);
-    print!("Enter a command: ");
-    let mut command = String::new();
-    io::stdin().read_line(&mut command);
-    command
+    if let Some(size) = cache_size() {
+        println!("Current cache size = {}", pure::human_size(size));
+    }
+    loop {
+        match prompt::input_or_reload("Enter a command: ") {
+            Some(command) => return command,
+            // Nothing here depends on the config, so only the status is shown
+            None => printer::print_config_status(&config::config_errors()),
+        }
+    }
 }
 
-pub fn show_man_loop() {
-    Command::new("clear").spawn();
-    // println!(docs);
-    println!("{}{}", " ".repeat(3), "=".repeat(30));
-    let mut command = String::new();
+/// The manual of a screen, at the bottom of the screen like other docs
+/// if it fits, or else in a pager
+pub fn show_man_loop(page: Page) {
+    let doc = page.doc();
+    let lines: Vec<&str> = doc.lines().collect();
+    // Rows left for a message and the prompt
+    let height = (utils::term_height() as usize).saturating_sub(2).max(1);
+    if lines.len() <= height {
+        printer::print_doc(doc);
+        prompt::input("Enter any key to return: ");
+        system().clear();
+        return;
+    }
+
+    let mut top = 0;
+    let mut query = String::new();
+    let mut message = String::new();
     loop {
-        print!("\n\nEnter any key to return: ");
-        match io::stdin().read_line(&mut command) {
-            Ok(_) => break,
-            _ => continue,
+        system().clear();
+        let shown = manual::visible(lines.len(), top, height);
+        for line in &lines[shown.clone()] {
+            println!("{}", line);
         }
-    };
-    Command::new("clear").spawn();
+        println!("{}", message);
+        message.clear();
+        let command = prompt::input(&format!(
+            "Lines {}-{} of {}; {}/{} scroll; enter for the next page; {}earch; {}ext match; {}uit: ",
+            shown.start + 1,
+            shown.end,
+            lines.len(),
+            colors::j(),
+            colors::k(),
+            colors::slash(),
+            n(),
+            q()
+        ));
+        let search_from = match command.as_str() {
+            "" | "f" => {
+                top += height;
+                None
+            }
+            "u" => {
+                top = top.saturating_sub(height);
+                None
+            }
+            "j" => {
+                top += 1;
+                None
+            }
+            "k" => {
+                top = top.saturating_sub(1);
+                None
+            }
+            "g" => {
+                top = 0;
+                None
+            }
+            "G" => {
+                top = lines.len();
+                None
+            }
+            "n" if !query.is_empty() => Some(top + 1),
+            "q" | "b" => break,
+            command if command.starts_with('/') && command.len() > 1 => {
+                query = command[1..].to_string();
+                Some(top)
+            }
+            _ => {
+                message = "Invalid command!".to_string();
+                None
+            }
+        };
+        if let Some(start) = search_from {
+            match manual::search(&lines, &query, start) {
+                // The match goes on the top line, unless it is on the last page
+                Some(idx) => top = idx,
+                None => message = format!("{} not found", query),
+            }
+        }
+        top = manual::clamp_top(top, lines.len(), height);
+    }
+    system().clear();
 }
 
 pub fn clear_cache_loop() {
     println!("Do you want to remove all cached images?");
-    println!("This will not remove images you explicitly downloaded to ~/Downloads.");
+    println!(
+        "This will not remove images you explicitly downloaded to {}.",
+        pure::template_root(&config::download_template_config()).display()
+    );
     println!("Directory to be deleted: {}", KONEKODIR);
     let mut command = String::new();
     loop {
@@ -59,19 +172,24 @@ pub fn clear_cache_loop() {
             _ => println!("Operation aborted!")
         }
     }
-    Command::new("clear").spawn();
+    *CACHE_SIZE.lock().unwrap() = None;
+    system().clear();
 }
 
 pub fn info_screen_loop() {
     let messages = [
         &format!("koneko こねこ version {} beta\n", __version__),
-        "Browse pixiv in the terminal using kitty's icat to display",
-        "images with images embedded in the terminal\n",
+        "Browse pixiv in the terminal using kitty, sixel, iTerm2 or",
+        "unicode block graphics to display images in the terminal\n",
         "1. View an artist's illustrations",
         "2. View a post (support multiple images)",
         "3. View artists you followed",
         "4. Search for artists and browse their works.",
-        "5. View latest illustrations from artist you follow.\n",
+        "5. View latest illustrations from artist you follow.",
+        "6. Search for illustrations by tag.",
+        "7. View daily, weekly and monthly rankings.",
+        "8. View illustrations recommended for you.",
+        "9. View your public and private bookmarks.\n",
         "Thank you for using koneko!",
         "Please star, report bugs and contribute in:",
         "https://github.com/twenty5151/koneko",
@@ -80,8 +198,7 @@ pub fn info_screen_loop() {
         "Welcome image: https://www.pixiv.net/en/artworks/71471144",
         "Current image: https://www.pixiv.net/en/artworks/79494300",
     ];
-    messages.iter().for_each(|m| println!("{}", m));
-    // TODO: show pixcat image
+    print_beside_pic(&messages, utils::INFO_PIC);
     let mut command = String::new();
     loop {
         print!("\nEnter any key to return: ");
@@ -91,3 +208,15 @@ pub fn info_screen_loop() {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_width() {
+        assert_eq!(text_width("GPLv3 licensed"), 14);
+        assert_eq!(text_width("koneko こねこ"), 13);
+        assert_eq!(text_width(""), 0);
+    }
+}
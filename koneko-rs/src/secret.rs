@@ -0,0 +1,95 @@
+//! The refresh token can be kept in a file encrypted with a passphrase, instead
+//! of in plain text in config.ini. The key is derived from the passphrase with
+//! argon2, and the token is sealed with ChaCha20-Poly1305
+
+use std::fs;
+use std::path::Path;
+
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand_core::{OsRng, RngCore};
+
+use crate::files;
+use crate::pure;
+
+pub const SECRETPATH: &str = "~/.config/koneko/secret";
+
+/// Marks the file format, so a future change to it can still read old files
+const MAGIC: &[u8] = b"koneko-secret-v1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Option<Key> {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .ok()?;
+    Some(key)
+}
+
+/// The magic, salt and nonce, followed by the encrypted secret
+pub fn encrypt(secret: &str, passphrase: &str) -> Option<Vec<u8>> {
+    let mut salt = [0; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    let ciphertext = cipher.encrypt(&nonce, secret.as_bytes()).ok()?;
+    Some([MAGIC, &salt, &nonce, &ciphertext].concat())
+}
+
+/// None if the passphrase is wrong or the data is not an encrypted secret
+pub fn decrypt(data: &[u8], passphrase: &str) -> Option<String> {
+    let data = data.strip_prefix(MAGIC)?;
+    if data.len() < SALT_LEN + NONCE_LEN {
+        return None;
+    }
+    let (salt, data) = data.split_at(SALT_LEN);
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, salt)?);
+    let secret = cipher.decrypt(Nonce::from_slice(nonce), ciphertext).ok()?;
+    String::from_utf8(secret).ok()
+}
+
+pub fn secret_exists() -> bool {
+    pure::expand_home(Path::new(SECRETPATH)).exists()
+}
+
+pub fn write_secret(secret: &str, passphrase: &str) -> Option<()> {
+    files::write_private(
+        &pure::expand_home(Path::new(SECRETPATH)),
+        &encrypt(secret, passphrase)?,
+    )
+    .ok()
+}
+
+pub fn read_secret(passphrase: &str) -> Option<String> {
+    decrypt(
+        &fs::read(pure::expand_home(Path::new(SECRETPATH))).ok()?,
+        passphrase,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let data = encrypt("HunTer2", "correct horse").unwrap();
+        assert!(data.starts_with(MAGIC));
+        assert!(!data.windows(7).any(|window| window == b"HunTer2"));
+        assert_eq!(decrypt(&data, "correct horse").unwrap(), "HunTer2");
+        assert_eq!(decrypt(&data, "wrong horse"), None);
+
+        // A new salt and nonce every time
+        assert_ne!(encrypt("HunTer2", "correct horse").unwrap(), data);
+    }
+
+    #[test]
+    fn test_decrypt_invalid() {
+        assert_eq!(decrypt(b"", "correct horse"), None);
+        assert_eq!(decrypt(b"username=koneko", "correct horse"), None);
+        assert_eq!(decrypt(&[MAGIC, &[0; 20]].concat(), "correct horse"), None);
+    }
+}
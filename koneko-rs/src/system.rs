@@ -0,0 +1,279 @@
+//! Everything koneko asks of the system outside of itself: clearing the
+//! screen, opening links and files in other programs, measuring the cache,
+//! and fetching files. Tests swap in a `Recorder` instead of the real system
+
+use std::cell::RefCell;
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use crate::config;
+use crate::printer;
+
+// i.pximg.net refuses requests without a pixiv referer
+const REFERER: &str = "https://app-api.pixiv.net/";
+
+/// Used when neither the config nor $BROWSER name a program
+#[cfg(target_os = "macos")]
+const DEFAULT_OPENER: &str = "open";
+#[cfg(not(target_os = "macos"))]
+const DEFAULT_OPENER: &str = "xdg-open";
+
+pub trait System: Send + Sync {
+    fn clear(&self);
+
+    fn open_url(&self, url: &str) -> Option<()>;
+
+    fn open_file(&self, path: &Path) -> Option<()>;
+
+    /// The total size in bytes of the files in a directory and its subdirectories
+    fn disk_usage(&self, dir: &Path) -> Option<u64>;
+
+    /// Downloads `url` to `filepath`
+    fn fetch(&self, url: &str, filepath: &Path) -> Option<()>;
+}
+
+pub struct Native;
+
+impl System for Native {
+    fn clear(&self) {
+        // Like `clear`, which also clears the scrollback
+        printer::write("\x1b[H\x1b[2J\x1b[3J");
+    }
+
+    fn open_url(&self, url: &str) -> Option<()> {
+        browser_commands(&config::browser_command_config(), env::var("BROWSER").ok())
+            .iter()
+            .find_map(|command| run_detached(command, url))
+    }
+
+    fn open_file(&self, path: &Path) -> Option<()> {
+        let command = config::image_viewer_command_config();
+        let command = if command.is_empty() {
+            DEFAULT_OPENER
+        } else {
+            &command
+        };
+        run_detached(command, path.to_str()?)
+    }
+
+    fn disk_usage(&self, dir: &Path) -> Option<u64> {
+        let mut total = 0;
+        for entry in fs::read_dir(dir).ok()? {
+            let entry = entry.ok()?;
+            // Symlinks are counted as themselves, rather than what they point to
+            let meta = entry.metadata().ok()?;
+            total += if meta.is_dir() {
+                self.disk_usage(&entry.path()).unwrap_or(0)
+            } else {
+                meta.len()
+            };
+        }
+        Some(total)
+    }
+
+    fn fetch(&self, url: &str, filepath: &Path) -> Option<()> {
+        let response = ureq::get(url).set("Referer", REFERER).call().ok()?;
+        // Write to a hidden file first, so a half-finished download is never displayed
+        let partpath =
+            filepath.with_file_name(format!(".{}.part", filepath.file_name()?.to_str()?));
+        let mut file = File::create(&partpath).ok()?;
+        io::copy(&mut response.into_reader(), &mut file).ok()?;
+        fs::rename(partpath, filepath).ok()
+    }
+}
+
+/// The program and its arguments, from a command where `%s` stands for `arg`.
+/// If there is no `%s`, `arg` goes at the end, as $BROWSER does
+fn command_line(command: &str, arg: &str) -> Option<(String, Vec<String>)> {
+    let mut words = command
+        .split_whitespace()
+        .map(|word| word.replace("%s", arg));
+    let program = words.next()?;
+    let mut args: Vec<String> = words.collect();
+    if !command.contains("%s") {
+        args.push(arg.to_string());
+    }
+    Some((program, args))
+}
+
+/// Starts a program without waiting for it to close
+fn run_detached(command: &str, arg: &str) -> Option<()> {
+    let (program, args) = command_line(command, arg)?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    // Waited on elsewhere, so it does not linger as a zombie once it exits
+    thread::spawn(move || child.wait());
+    Some(())
+}
+
+/// The commands to try in turn: the one in the config, or else each one
+/// in $BROWSER, which separates them with colons, and then the default
+fn browser_commands(configured: &str, browser_env: Option<String>) -> Vec<String> {
+    if !configured.is_empty() {
+        return vec![configured.to_string()];
+    }
+    let mut commands: Vec<String> = browser_env
+        .unwrap_or_default()
+        .split(':')
+        .filter(|command| !command.trim().is_empty())
+        .map(|command| command.to_string())
+        .collect();
+    commands.push(DEFAULT_OPENER.to_string());
+    commands
+}
+
+thread_local! {
+    static OVERRIDE: RefCell<Option<Arc<dyn System>>> = RefCell::new(None);
+}
+
+pub fn system() -> Arc<dyn System> {
+    OVERRIDE.with(|system| system.borrow().clone().unwrap_or_else(|| Arc::new(Native)))
+}
+
+/// Like `thread::spawn`, but `system()` on the new thread is the same as on
+/// this one, so a fake also sees what is done in the background
+pub fn spawn<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> JoinHandle<T> {
+    let current = OVERRIDE.with(|system| system.borrow().clone());
+    thread::spawn(move || {
+        OVERRIDE.with(|system| *system.borrow_mut() = current);
+        f()
+    })
+}
+
+/// Runs `f` with `system()` on this thread, and threads started with `spawn`,
+/// returning `fake`
+#[cfg(test)]
+pub fn with_system<T>(fake: Arc<dyn System>, f: impl FnOnce() -> T) -> T {
+    use scopeguard::defer;
+
+    OVERRIDE.with(|system| *system.borrow_mut() = Some(fake));
+    defer! {
+        OVERRIDE.with(|system| *system.borrow_mut() = None);
+    };
+    f()
+}
+
+/// Records what was asked of it instead of doing it
+#[cfg(test)]
+#[derive(Default)]
+pub struct Recorder {
+    pub calls: std::sync::Mutex<Vec<String>>,
+    /// What `disk_usage` answers
+    pub usage: Option<u64>,
+}
+
+#[cfg(test)]
+impl Recorder {
+    fn record(&self, call: String) {
+        self.calls.lock().unwrap().push(call);
+    }
+}
+
+#[cfg(test)]
+impl System for Recorder {
+    fn clear(&self) {
+        self.record("clear".to_string());
+    }
+
+    fn open_url(&self, url: &str) -> Option<()> {
+        self.record(format!("open_url {}", url));
+        Some(())
+    }
+
+    fn open_file(&self, path: &Path) -> Option<()> {
+        self.record(format!("open_file {}", path.display()));
+        Some(())
+    }
+
+    fn disk_usage(&self, dir: &Path) -> Option<u64> {
+        self.record(format!("disk_usage {}", dir.display()));
+        self.usage
+    }
+
+    fn fetch(&self, url: &str, filepath: &Path) -> Option<()> {
+        self.record(format!("fetch {} {}", url, filepath.display()));
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_line() {
+        assert_eq!(
+            command_line("firefox --new-tab", "https://www.pixiv.net"),
+            Some((
+                "firefox".to_string(),
+                vec!["--new-tab".to_string(), "https://www.pixiv.net".to_string()]
+            ))
+        );
+        assert_eq!(
+            command_line("feh -F %s --auto-zoom", "/tmp/a.png"),
+            Some((
+                "feh".to_string(),
+                vec![
+                    "-F".to_string(),
+                    "/tmp/a.png".to_string(),
+                    "--auto-zoom".to_string()
+                ]
+            ))
+        );
+        assert_eq!(command_line("  ", "/tmp/a.png"), None);
+    }
+
+    #[test]
+    fn test_browser_commands() {
+        assert_eq!(
+            browser_commands("firefox", Some("chromium".to_string())),
+            vec!["firefox"]
+        );
+        assert_eq!(
+            browser_commands("", Some("w3m %s::chromium".to_string())),
+            vec!["w3m %s", "chromium", DEFAULT_OPENER]
+        );
+        assert_eq!(browser_commands("", None), vec![DEFAULT_OPENER]);
+    }
+
+    #[test]
+    fn test_disk_usage() {
+        let dir = env::temp_dir().join("koneko-test-disk-usage");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("2232374/1")).unwrap();
+        fs::write(dir.join("a.jpg"), [0; 100]).unwrap();
+        fs::write(dir.join("2232374/1/b.jpg"), [0; 23]).unwrap();
+        assert_eq!(Native.disk_usage(&dir), Some(123));
+        assert_eq!(Native.disk_usage(&dir.join("missing")), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_with_system() {
+        let recorder = Arc::new(Recorder::default());
+        with_system(recorder.clone(), || {
+            system().clear();
+            system().open_url("https://www.pixiv.net");
+            spawn(|| system().fetch("https://i.pximg.net/a.png", Path::new("/tmp/a.png")))
+                .join()
+                .unwrap();
+        });
+        assert_eq!(
+            *recorder.calls.lock().unwrap(),
+            vec![
+                "clear",
+                "open_url https://www.pixiv.net",
+                "fetch https://i.pximg.net/a.png /tmp/a.png"
+            ]
+        );
+    }
+}
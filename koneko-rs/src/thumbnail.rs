@@ -0,0 +1,112 @@
+//! Thumbnails scaled to the exact pixel size of a grid slot, cached as png
+//! next to the originals so that redrawing a page does not decode them again
+
+use std::fs;
+use std::thread;
+use std::path::{Path, PathBuf};
+
+use image::GenericImageView;
+use image::imageops::FilterType;
+
+use crate::pure;
+use crate::renderer;
+
+/// Hidden, so it is skipped when listing the images in a directory
+const THUMBNAIL_DIR: &str = ".thumbnails";
+
+/// Where the thumbnail of an image is cached, keyed by the box it was scaled to fit
+pub fn thumbnail_path(original: &Path, bounds: (u32, u32)) -> Option<PathBuf> {
+    Some(
+        original
+            .parent()?
+            .join(THUMBNAIL_DIR)
+            .join(format!("{}x{}", bounds.0, bounds.1))
+            .join(format!("{}.png", original.file_name()?.to_str()?)),
+    )
+}
+
+/// A thumbnail is stale if the original was downloaded again after it was made
+fn is_fresh(original: &Path, thumbnail: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    match (modified(original), modified(thumbnail)) {
+        (Some(original), Some(thumbnail)) => thumbnail >= original,
+        _ => false,
+    }
+}
+
+/// The thumbnail of an image that fits in `bounds` pixels, made and cached if needed
+pub fn cached(original: &Path, bounds: (u32, u32)) -> Option<PathBuf> {
+    let path = thumbnail_path(original, bounds)?;
+    if is_fresh(original, &path) {
+        return Some(path);
+    }
+    let image = renderer::open_image(original)?;
+    let (width, height) = pure::fit_size(image.dimensions(), bounds);
+    let thumbnail = image.resize_exact(width, height, FilterType::Triangle);
+
+    fs::create_dir_all(path.parent()?).ok()?;
+    // Like downloads, write to a hidden file first so a half-written png is never shown
+    let partpath = path.with_file_name(format!(".{}.part", path.file_name()?.to_str()?));
+    thumbnail
+        .save_with_format(&partpath, image::ImageFormat::Png)
+        .ok()?;
+    fs::rename(partpath, &path).ok()?;
+    Some(path)
+}
+
+/// Makes the thumbnails of every image at once, before they are drawn one by one
+pub fn cache_all(originals: &[PathBuf], bounds: (u32, u32)) {
+    let handles: Vec<_> = originals
+        .iter()
+        .filter(|original| {
+            thumbnail_path(original, bounds).is_some_and(|path| !is_fresh(original, &path))
+        })
+        .map(|original| {
+            let original = original.clone();
+            thread::spawn(move || cached(&original, bounds))
+        })
+        .collect();
+    for handle in handles {
+        let _ = handle.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::RgbImage;
+
+    #[test]
+    fn test_thumbnail_path() {
+        assert_eq!(
+            thumbnail_path(Path::new("/cache/2232374/1/001_hi.jpg"), (180, 160)),
+            Some(PathBuf::from(
+                "/cache/2232374/1/.thumbnails/180x160/001_hi.jpg.png"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_cached() {
+        let dir = std::env::temp_dir().join("koneko-test-thumbnail");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let original = dir.join("001_wide.png");
+        RgbImage::new(400, 200).save(&original).unwrap();
+
+        let path = cached(&original, (100, 100)).unwrap();
+        assert_eq!(image::image_dimensions(&path).unwrap(), (100, 50));
+        assert!(is_fresh(&original, &path));
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        assert_eq!(cached(&original, (100, 100)), Some(path.clone()));
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), modified);
+
+        cache_all(std::slice::from_ref(&original), (40, 40));
+        let path = thumbnail_path(&original, (40, 40)).unwrap();
+        assert_eq!(image::image_dimensions(&path).unwrap(), (40, 20));
+
+        assert_eq!(cached(&dir.join("missing.jpg"), (40, 40)), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
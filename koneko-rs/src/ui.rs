@@ -1,19 +1,744 @@
-use crate::data;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::data::{self, Data};
+use crate::dedupe;
+use crate::api::{Api, Restrict};
+use crate::download;
+use crate::files;
+use crate::lscat;
+use crate::printer;
+use crate::prompt;
+use crate::pure;
+use crate::renderer;
+use crate::system::{self, system};
 use crate::utils;
+use crate::config;
+use crate::colors::t;
+
+impl data::Gallery {
+    pub fn show_page(&self) {
+        if config::text_only_config() {
+            lscat::clear();
+            printer::print_gallery_text(
+                self.current_illusts().unwrap(),
+                config::ncols_config(),
+                &self.labels(),
+            );
+        } else {
+            download::init_download(self);
+            lscat::clear();
+            lscat::show_instant(self, &self.labels());
+            printer::print_multiple_imgs(self.current_illusts().unwrap());
+        }
+        println!("Page {}", self.page_num);
+        printer::print_hidden(self.hidden_count());
+        if let data::GalleryMode::Bookmarks { restrict, .. } = &self.mode {
+            println!("Showing {} bookmarks; {}oggle public/private", restrict.as_str(), t());
+        }
+    }
+
+    pub fn next_page(&mut self, api: &Api) {
+        if self.fetch_next_page(api).is_some() {
+            self.page_num += 1;
+            self.show_page();
+        }
+    }
+
+    /// Pages already in the cache are never requested again, because some modes
+    /// (like recommended illusts) give different results on every request
+    fn fetch_next_page(&mut self, api: &Api) -> Option<()> {
+        if self.all_pages_cache.contains_key(&(self.page_num + 1)) {
+            return Some(());
+        }
+        let next_url = match self.next_url() {
+            Some(url) => url,
+            None => {
+                println!("This is the last page!");
+                return None;
+            }
+        };
+        match api.next_page(&next_url) {
+            Some(raw) => {
+                self.insert_page(self.page_num + 1, &raw);
+                Some(())
+            }
+            None => {
+                println!("Failed to fetch the next page!");
+                None
+            }
+        }
+    }
+
+    pub fn previous_page(&mut self) {
+        if self.page_num <= 1 {
+            println!("This is the first page!");
+            return;
+        }
+        self.page_num -= 1;
+        self.show_page();
+    }
+
+    /// Switches between public and private bookmarks, starting from page 1
+    pub fn toggle_restrict(&mut self, api: &Api) {
+        let (user_id, restrict, tag) = match &self.mode {
+            data::GalleryMode::Bookmarks {
+                user_id,
+                restrict,
+                tag,
+            } => (user_id.clone(), restrict.toggle(), tag.clone()),
+            _ => {
+                println!("Only bookmarks can be toggled between public and private!");
+                return;
+            }
+        };
+        let raw = match api.user_bookmarks_illust(&user_id, restrict, tag.as_deref()) {
+            Some(raw) => raw,
+            None => {
+                println!("Failed to fetch {} bookmarks!", restrict.as_str());
+                return;
+            }
+        };
+        self.reset(data::bookmarks_path(restrict, tag.as_deref()));
+        self.mode = data::GalleryMode::Bookmarks {
+            user_id,
+            restrict,
+            tag,
+        };
+        self.update(&raw);
+        self.show_page();
+    }
+
+    /// Redraws the current page from the cached JSON, keeping the
+    /// downloaded images and their thumbnails
+    pub fn reload(&self) {
+        self.show_page();
+    }
+
+    pub fn view_image(&mut self, api: &Api, post_number: i32) {
+        let post_json = self.post_json(post_number).clone();
+        let image_id = self.image_id(post_number);
+        let mut image = data::Image::new(&post_json, &image_id, false);
+        image.display_initial();
+        prompt::image_prompt(api, &mut image);
+        self.set_bookmarked(post_number, image.is_bookmarked);
+        self.show_page();
+    }
+
+    pub fn open_link(&self, post_number: i32) {
+        utils::open_link_num(self, post_number)
+    }
+
+    pub fn download_image(&self, post_number: i32) {
+        let post_json = self.post_json(post_number).clone();
+        let image_id = self.image_id(post_number);
+        data::Image::new(&post_json, &image_id, false).download_image();
+    }
+
+    pub fn bookmark(
+        &mut self,
+        api: &Api,
+        post_number: i32,
+        restrict: Restrict,
+        tags: &[String],
+    ) -> Option<()> {
+        api.illust_bookmark_add(&self.image_id(post_number), restrict, tags)?;
+        self.set_bookmarked(post_number, true);
+        Some(())
+    }
+
+    pub fn unbookmark(&mut self, api: &Api, post_number: i32) -> Option<()> {
+        api.illust_bookmark_delete(&self.image_id(post_number))?;
+        self.set_bookmarked(post_number, false);
+        Some(())
+    }
+}
+
+/// Keeps the logged in user's cached following pages in line with a follow,
+/// so mode 3 shows the change without reloading
+fn follow_in_cache(api: &Api, restrict: Restrict, user_preview: &Value) {
+    if api.user_id.is_empty() {
+        return;
+    }
+    let user_id = user_preview["user"]["id"].to_string();
+    // Following again with the other restrict moves the user between the lists
+    files::unfollow_in_cache(&data::following_path(&api.user_id, restrict.toggle()), &user_id);
+    files::follow_in_cache(&data::following_path(&api.user_id, restrict), user_preview);
+}
+
+/// Lists downloads that look like the image about to be downloaded, and
+/// asks whether to download it anyway. True if there are none
+fn confirm_duplicates(duplicates: &[(PathBuf, u32)]) -> bool {
+    if duplicates.is_empty() {
+        return true;
+    }
+    println!("This image looks like one already downloaded:");
+    for (path, distance) in duplicates {
+        println!("   {} ({} of 64 bits differ)", path.display(), distance);
+    }
+    prompt::input("Download anyway? [y/N] ") == "y"
+}
+
+fn unfollow_in_cache(api: &Api, user_id: &str) {
+    if api.user_id.is_empty() {
+        return;
+    }
+    for restrict in [Restrict::Public, Restrict::Private].iter() {
+        files::unfollow_in_cache(&data::following_path(&api.user_id, *restrict), user_id);
+    }
+}
+
+impl data::UserData {
+    pub fn show_page(&self) {
+        if config::text_only_config() {
+            lscat::clear();
+            printer::print_users_text(&self.all_pages_cache[&self.page_num]["user_previews"]);
+        } else {
+            download::init_download(self);
+            lscat::clear();
+            lscat::show_users(self);
+        }
+        println!("Page {}", self.page_num);
+        printer::print_hidden(self.hidden);
+    }
+
+    pub fn next_page(&mut self, api: &Api) {
+        if let Some(raw) = self.fetch_page(api, self.page_num + 1) {
+            self.page_num += 1;
+            self.update(&raw);
+            self.show_page();
+        }
+    }
+
+    /// Pages are read from memory, then the cache on disk, and only the
+    /// next page is ever requested
+    fn fetch_page(&self, api: &Api, page_num: i32) -> Option<Value> {
+        if let Some(raw) = self.all_pages_cache.get(&page_num) {
+            return Some(raw.clone());
+        }
+        if let Some(raw) = files::read_following_page(&self.main_path, page_num) {
+            return Some(raw);
+        }
+        if page_num != self.page_num + 1 {
+            println!("Page {} is not cached! Enter mode 3 again to fetch it", page_num);
+            return None;
+        }
+        let next_url = match self.next_url() {
+            Some(url) => url,
+            None => {
+                println!("This is the last page!");
+                return None;
+            }
+        };
+        match api.next_page(&next_url) {
+            Some(raw) => {
+                files::write_following_page(&self.main_path, page_num, &raw);
+                Some(raw)
+            }
+            None => {
+                println!("Failed to fetch the next page!");
+                None
+            }
+        }
+    }
+
+    pub fn previous_page(&mut self, api: &Api) {
+        if self.page_num <= 1 {
+            println!("This is the first page!");
+            return;
+        }
+        if let Some(raw) = self.fetch_page(api, self.page_num - 1) {
+            self.page_num -= 1;
+            self.update(&raw);
+            self.show_page();
+        }
+    }
+
+    /// Re-reads the current page from the cached JSON, which following and
+    /// unfollowing edit in place, and redraws it with the images already on disk
+    pub fn reload(&mut self) {
+        let raw = match files::read_following_page(&self.main_path, self.page_num) {
+            Some(raw) => raw,
+            None => {
+                println!("Page {} is not cached!", self.page_num);
+                return;
+            }
+        };
+        self.update(&raw);
+        self.show_page();
+    }
+
+    pub fn view_artist(&self, api: &Api, selected_user_num: i32) {
+        let artist_user_id = self.artist_user_id(selected_user_num);
+        let raw = match api.user_illusts(&artist_user_id) {
+            Some(raw) => raw,
+            None => {
+                println!("Failed to fetch the artist's illusts!");
+                return;
+            }
+        };
+        let mut gdata = data::Gallery::new(
+            Path::new(crate::KONEKODIR).join(&artist_user_id),
+            data::GalleryMode::Artist,
+        );
+        gdata.update(&raw);
+        prompt::gallery_like_prompt(api, &mut gdata);
+        self.show_page();
+    }
+
+    pub fn follow(&mut self, api: &Api, selected_user_num: i32, restrict: Restrict) -> Option<()> {
+        let user_preview = self.user_preview(selected_user_num).clone();
+        api.user_follow_add(&self.artist_user_id(selected_user_num), restrict)?;
+        follow_in_cache(api, restrict, &user_preview);
+        self.sync_with_cache();
+        Some(())
+    }
+
+    pub fn unfollow(&mut self, api: &Api, selected_user_num: i32) -> Option<()> {
+        let artist_user_id = self.artist_user_id(selected_user_num);
+        api.user_follow_delete(&artist_user_id)?;
+        unfollow_in_cache(api, &artist_user_id);
+        self.sync_with_cache();
+        Some(())
+    }
+
+    /// When this is the logged in user's own list, the cache on disk may have
+    /// just changed, so the pages in memory are read from it again
+    fn sync_with_cache(&mut self) {
+        let current_page = self.page_num;
+        if let Some(raw) = files::read_following_page(&self.main_path, current_page) {
+            self.all_pages_cache.retain(|&page_num, _| page_num == current_page);
+            self.update(&raw);
+        }
+    }
+}
 
 impl data::Image<'_> {
+    pub fn display_initial(&self) {
+        self.jump();
+    }
+
     pub fn open_image(&self) {
         utils::open_in_browser(self.image_id)
     }
 
-    pub fn download_image(&self) {}
-    pub fn show_full_res(&self) {}
-    pub fn next_image(&self) {}
-    pub fn previous_image(&self) {}
-    pub fn jump_to_image(&self, selected_image_num: i32) {}
-    fn jump(&self) {}
-    fn prefetch_next_image(&self) {}
-    pub fn leave(&self, force: bool) {}
-    pub fn start_preview(&self) {}
-    pub fn preview(&self) {}
+    /// Opens the page being shown in `image_viewer_command`
+    pub fn open_in_viewer(&self) {
+        let filepath = pure::expand_home(&self.filepath());
+        if !filepath.exists() {
+            println!("The image has not been downloaded yet!");
+        } else if system().open_file(&filepath).is_none() {
+            println!("Failed to open the image in a viewer!");
+        }
+    }
+
+    pub fn download_image(&self) {
+        self.download_page(self.page_num);
+    }
+
+    pub fn download_all_pages(&self) {
+        for page_num in 0..self.number_of_pages {
+            self.download_page(page_num);
+        }
+    }
+
+    /// Downloads the original of a page to where `download_template` says
+    fn download_page(&self, page_num: i32) {
+        let page_url = &self.page_urls[page_num as usize];
+        let template = config::download_template_config();
+        let targets: Vec<(String, PathBuf)> = [false, true]
+            .iter()
+            .map(|png| {
+                let (url, _) = pure::full_image_details(page_url, *png);
+                let filepath =
+                    pure::generate_filepath(&template, &self.template_fields(page_num, &url));
+                (url, filepath)
+            })
+            .collect();
+        if let Some((_, filepath)) = targets.iter().find(|(_, filepath)| filepath.exists()) {
+            println!("{} already exists!", filepath.display());
+            return;
+        }
+        // A page that was shown is already in the cache, so it can be compared before downloading
+        let cached = self.download_path.join(pure::split_backslash_last(page_url));
+        let shown = pure::expand_home(&cached);
+        if shown.exists() && !confirm_duplicates(&dedupe::downloaded_duplicates(&shown)) {
+            println!("Download cancelled");
+            return;
+        }
+        for (url, filepath) in targets {
+            let created = filepath
+                .parent()
+                .is_none_or(|dir| fs::create_dir_all(dir).is_ok());
+            if created && download::download_url(&url, &filepath).is_some() {
+                let filepath = if config::webp_to_png_config() {
+                    download::webp_to_png(&filepath).unwrap_or(filepath)
+                } else {
+                    filepath
+                };
+                println!("Image downloaded at {}", filepath.display());
+                dedupe::record_download(&filepath);
+                return;
+            }
+        }
+        println!("Failed to download the image!");
+    }
+
+    pub fn bookmark(&mut self, api: &Api, restrict: Restrict, tags: &[String]) -> Option<()> {
+        api.illust_bookmark_add(self.image_id, restrict, tags)?;
+        self.is_bookmarked = true;
+        Some(())
+    }
+
+    pub fn unbookmark(&mut self, api: &Api) -> Option<()> {
+        api.illust_bookmark_delete(self.image_id)?;
+        self.is_bookmarked = false;
+        Some(())
+    }
+
+    pub fn follow(&mut self, api: &Api, restrict: Restrict) -> Option<()> {
+        api.user_follow_add(&self.artist_user_id, restrict)?;
+        self.is_followed = true;
+        follow_in_cache(api, restrict, &self.artist_preview());
+        Some(())
+    }
+
+    pub fn unfollow(&mut self, api: &Api) -> Option<()> {
+        api.user_follow_delete(&self.artist_user_id)?;
+        self.is_followed = false;
+        unfollow_in_cache(api, &self.artist_user_id);
+        Some(())
+    }
+
+    /// Opens the illusts related to this post in a gallery. Going back
+    /// from the gallery shows this post again, at the same page
+    pub fn view_related(&self, api: &Api) {
+        let raw = match api.illust_related(self.image_id) {
+            Some(raw) => raw,
+            None => {
+                println!("Failed to fetch related illusts!");
+                return;
+            }
+        };
+        let mut gdata = data::Gallery::new(
+            Path::new(crate::KONEKODIR)
+                .join("related")
+                .join(self.image_id),
+            data::GalleryMode::Related,
+        );
+        gdata.update(&raw);
+        if gdata.all_urls().is_empty() {
+            println!("No related illusts found!");
+            return;
+        }
+        prompt::gallery_like_prompt(api, &mut gdata);
+        self.jump();
+    }
+
+    /// Shows the original of the current page, which can be zoomed and panned
+    /// in `prompt::full_res_prompt`. The normal view is redrawn afterwards
+    pub fn show_full_res(&self) {
+        if config::text_only_config() {
+            println!("Full res view needs images; open it in the browser instead");
+            return;
+        }
+        let original = match self
+            .download_original()
+            .and_then(|path| renderer::open_image(&path))
+        {
+            Some(original) => original,
+            None => {
+                println!("Failed to download the full res image!");
+                return;
+            }
+        };
+        prompt::full_res_prompt(&original);
+        self.jump();
+    }
+
+    /// Downloads the original of the current page into `download_path/original`,
+    /// trying jpg then png, unless it is already there
+    fn download_original(&self) -> Option<PathBuf> {
+        let dir = self.download_path.join("original");
+        for png in [false, true].iter() {
+            let url = pure::change_url_to_full(self.current_url(), *png);
+            let filepath = pure::expand_home(&dir.join(pure::split_backslash_last(&url)));
+            if filepath.exists() || download::download_url_to_dir(&url, &dir).is_some() {
+                return Some(filepath);
+            }
+        }
+        None
+    }
+
+    pub fn next_image(&mut self) {
+        if self.page_num + 1 >= self.number_of_pages {
+            println!("This is the last image in the post!");
+            return;
+        }
+        self.page_num += 1;
+        self.jump();
+    }
+
+    pub fn previous_image(&mut self) {
+        if self.page_num == 0 {
+            println!("This is the first image in the post!");
+            return;
+        }
+        self.page_num -= 1;
+        self.jump();
+    }
+
+    pub fn jump_to_image(&mut self, selected_image_num: i32) {
+        if selected_image_num < 1 || selected_image_num > self.number_of_pages {
+            println!("Invalid number!");
+            return;
+        }
+        self.page_num = selected_image_num - 1;
+        self.jump();
+    }
+
+    fn jump(&self) {
+        if config::text_only_config() {
+            lscat::clear();
+            println!(
+                "{} by {}",
+                self.raw["title"].as_str().unwrap_or(""),
+                self.raw["user"]["name"].as_str().unwrap_or("")
+            );
+            println!("{}", self.current_url());
+            println!("{}", self.info_line());
+            return;
+        }
+        let filepath = pure::expand_home(&self.filepath());
+        if !filepath.exists() {
+            download::download_url_to_dir(self.current_url(), &self.download_path);
+        }
+        lscat::clear();
+        if config::check_image_preview() && self.number_of_pages > 1 {
+            let bottom_rows = config::image_text_offset() + lscat::previews_height();
+            lscat::show_center(&filepath, bottom_rows);
+            self.preview();
+        } else {
+            lscat::show_center(&filepath, lscat::CENTER_TEXT_ROWS);
+        }
+        println!("{}", self.info_line());
+        self.prefetch_next_image();
+    }
+
+    fn prefetch_next_image(&self) {
+        if self.page_num + 1 >= self.number_of_pages {
+            return;
+        }
+        let url = self.next_img_url().to_string();
+        let download_path = self.download_path.clone();
+        system::spawn(move || download::download_url_to_dir(&url, &download_path));
+    }
+
+    pub fn leave(&self, _force: bool) {}
+
+    /// Downloads the small thumbnail of every page in the post, for the previews
+    /// strip, and returns where they are in page order
+    pub fn start_preview(&self) -> Vec<PathBuf> {
+        let preview_path = pure::expand_home(&self.download_path.join("previews"));
+        let urls = pure::page_urls_in_post(self.raw, "square_medium");
+        let handles: Vec<_> = urls
+            .iter()
+            .filter(|url| !preview_path.join(pure::split_backslash_last(url)).exists())
+            .map(|url| {
+                let (url, preview_path) = (url.to_string(), preview_path.clone());
+                system::spawn(move || download::download_url_to_dir(&url, &preview_path))
+            })
+            .collect();
+        for handle in handles {
+            let _ = handle.join();
+        }
+        urls.iter()
+            .map(|url| preview_path.join(pure::split_backslash_last(url)))
+            .collect()
+    }
+
+    pub fn preview(&self) {
+        lscat::show_previews(&self.start_preview(), self.page_num as usize);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::collections::HashMap;
+
+    use rstest::*;
+    use serde_json::Value;
+
+    use crate::mock::MockApi;
+    use crate::data::GalleryMode;
+    use crate::filter::Filter;
+
+    #[fixture]
+    fn gallery_json() -> Value {
+        let file = fs::File::open("testing/files/mode1.json").unwrap();
+        serde_json::from_reader(file).unwrap()
+    }
+
+    #[fixture]
+    fn image_json() -> Value {
+        let file = fs::File::open("testing/files/mode2.json").unwrap();
+        let mode2: Value = serde_json::from_reader(file).unwrap();
+        mode2["illust"].clone()
+    }
+
+    #[rstest]
+    fn test_image_bookmark_round_trip(image_json: Value) {
+        let mock = MockApi::start(|_| (200, "{}".to_string()));
+        let api = mock.api();
+        let mut image = data::Image::new(&image_json, "76695217", false);
+
+        assert!(image
+            .bookmark(&api, Restrict::Private, &["御坂美琴".to_string(), "水着".to_string()])
+            .is_some());
+        assert!(image.is_bookmarked);
+        assert!(image.unbookmark(&api).is_some());
+        assert!(!image.is_bookmarked);
+
+        assert_eq!(
+            mock.paths(),
+            ["POST /v2/illust/bookmark/add", "POST /v1/illust/bookmark/delete"]
+        );
+        let requests = mock.requests.lock().unwrap();
+        assert_eq!(
+            requests[0].body,
+            "illust_id=76695217&restrict=private&tags%5B%5D=%E5%BE%A1%E5%9D%82%E7%BE%8E%E7%90%B4&tags%5B%5D=%E6%B0%B4%E7%9D%80"
+        );
+        assert_eq!(requests[1].body, "illust_id=76695217");
+    }
+
+    #[rstest]
+    fn test_image_bookmark_failed(image_json: Value) {
+        let mock = MockApi::start(|_| (404, "{}".to_string()));
+        let mut image = data::Image::new(&image_json, "76695217", false);
+        assert!(image.bookmark(&mock.api(), Restrict::Public, &[]).is_none());
+        assert!(!image.is_bookmarked);
+    }
+
+    #[rstest]
+    fn test_gallery_bookmark_round_trip(gallery_json: Value) {
+        let mock = MockApi::start(|_| (200, "{}".to_string()));
+        let api = mock.api();
+        let mut gdata = data::Gallery {
+            page_num: 1,
+            main_path: Path::new("search_illusts").to_path_buf(),
+            offset: 0,
+            all_pages_cache: HashMap::new(),
+            mode: GalleryMode::TagSearch,
+            filter: Filter::default(),
+            hidden_cache: HashMap::new(),
+        };
+        gdata.update(&gallery_json);
+
+        assert!(gdata.bookmark(&api, 1, Restrict::Public, &[]).is_some());
+        assert!(gdata.is_bookmarked(1));
+        assert!(gdata.unbookmark(&api, 1).is_some());
+        assert!(!gdata.is_bookmarked(1));
+        assert_eq!(
+            mock.requests.lock().unwrap()[0].body,
+            "illust_id=81501385&restrict=public"
+        );
+    }
+
+    #[rstest]
+    fn test_image_follow_round_trip(image_json: Value) {
+        let mock = MockApi::start(|_| (200, "{}".to_string()));
+        let api = mock.api();
+        let mut image = data::Image::new(&image_json, "76695217", false);
+
+        assert!(image.follow(&api, Restrict::Private).is_some());
+        assert!(image.is_followed);
+        assert!(image.unfollow(&api).is_some());
+        assert!(!image.is_followed);
+
+        assert_eq!(
+            mock.paths(),
+            ["POST /v1/user/follow/add", "POST /v1/user/follow/delete"]
+        );
+        let requests = mock.requests.lock().unwrap();
+        assert_eq!(
+            requests[0].body,
+            format!("user_id={}&restrict=private", image.artist_user_id)
+        );
+        assert_eq!(requests[1].body, format!("user_id={}", image.artist_user_id));
+    }
+
+    #[test]
+    fn test_user_unfollow() {
+        let file = fs::File::open("testing/files/mode3.json").unwrap();
+        let user_json: Value = serde_json::from_reader(file).unwrap();
+        let mock = MockApi::start(|_| (200, "{}".to_string()));
+        let mut api = mock.api();
+        // The cache helpers edit the logged in user's cached pages
+        api.user_id = "koneko-test-user-unfollow".to_string();
+        let main_path = data::following_path(&api.user_id, Restrict::Public);
+        // Someone the user unfollowed elsewhere, who is still in the list on screen
+        let mut cached = user_json.clone();
+        pure::remove_user_preview(&mut cached, "1510169");
+        files::write_following_page(&main_path, 1, &cached);
+        let mut udata = data::User {
+            page_num: 1,
+            main_path: main_path.clone(),
+            offset: 0,
+            mode: data::UserMode::Following {
+                user_id: "2232374".to_string(),
+                restrict: Restrict::Public,
+            },
+            filter: Filter::default(),
+        }
+        .update(&user_json);
+
+        assert!(udata.follow(&api, 1, Restrict::Public).is_some());
+        let cached = files::read_following_page(&main_path, 1).unwrap();
+        assert_eq!(cached["user_previews"][0]["user"]["id"], 1510169);
+        assert_eq!(cached["user_previews"].as_array().unwrap().len(), 30);
+
+        // The list was redrawn from the cache, with the followed user first
+        assert!(udata.unfollow(&api, 0).is_some());
+        let cached = files::read_following_page(&main_path, 1).unwrap();
+        assert!(!pure::has_user_preview(&cached, "1510169"));
+        assert_eq!(cached["user_previews"].as_array().unwrap().len(), 29);
+
+        let requests = mock.requests.lock().unwrap();
+        assert_eq!(requests[0].body, "user_id=1510169&restrict=public");
+        assert_eq!(requests[1].body, "user_id=1510169");
+        files::remove_following_cache(main_path.parent().unwrap());
+    }
+
+    #[rstest]
+    fn test_fetch_next_page_uses_cache(gallery_json: Value) {
+        let page2 = gallery_json.to_string();
+        let mock = MockApi::start(move |_| (200, page2.clone()));
+        let api = mock.api();
+        let mut gdata = data::Gallery {
+            page_num: 1,
+            main_path: Path::new("recommended").to_path_buf(),
+            offset: 0,
+            all_pages_cache: HashMap::new(),
+            mode: GalleryMode::Recommended,
+            filter: Filter::default(),
+            hidden_cache: HashMap::new(),
+        };
+        gdata.update(&gallery_json);
+
+        assert!(gdata.fetch_next_page(&api).is_some());
+        assert_eq!(
+            mock.paths(),
+            ["GET /v1/user/illusts?user_id=2232374&filter=for_ios&type=illust&offset=30"]
+        );
+        assert!(gdata.all_pages_cache.contains_key(&2));
+
+        // Back on page 1 after viewing page 2, going forward again does not refetch
+        gdata.page_num = 1;
+        assert!(gdata.fetch_next_page(&api).is_some());
+        assert_eq!(mock.paths().len(), 1);
+    }
 }
@@ -1,11 +1,12 @@
 use std::fs;
-use std::path::Path;
-use std::process::Command;
+use std::path::{Path, PathBuf};
 
 use crossterm::terminal;
 
 use crate::data;
 use crate::config;
+use crate::pure;
+use crate::system::system;
 use crate::KONEKODIR;
 
 #[macro_export]
@@ -34,6 +35,19 @@ pub fn term_height() -> u16 {
     terminal::size().unwrap().1
 }
 
+/// The size of one cell in pixels, if the terminal reports it
+pub fn cell_size() -> Option<(u32, u32)> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if result != 0 || size.ws_xpixel == 0 || size.ws_col == 0 || size.ws_row == 0 {
+        return None;
+    }
+    Some((
+        (size.ws_xpixel / size.ws_col) as u32,
+        (size.ws_ypixel / size.ws_row) as u32,
+    ))
+}
+
 // Calculations
 pub fn seq_coords_to_int(mut keyseqs: Vec<&str>) -> Option<i32> {
     let second_num: i32 = keyseqs.pop().unwrap().parse().unwrap();
@@ -54,51 +68,92 @@ pub fn find_number_map(x: i32, y: i32) -> Option<i32> {
 // IO related
 pub fn open_in_browser(image_id: &str) {
     let link = format!("https://www.pixiv.net/artworks/{}", image_id);
-    Command::new("xdg-open")
-        .arg(&link)
-        .spawn()
-        .unwrap_or_else(|_| panic!("xdg-open not installed!"));
-    println!("Opened {} in browser!", link);
+    match system().open_url(&link) {
+        Some(_) => println!("Opened {} in browser!", link),
+        None => println!("Failed to open {} in a browser!", link),
+    }
 }
 
-pub fn open_link_coords(data: data::Gallery, first_num: i32, second_num: i32) {
+pub fn open_link_coords(data: &data::Gallery, first_num: i32, second_num: i32) {
     match find_number_map(first_num, second_num) {
         Some(n) => open_link_num(data, n),
         None => println!("Invalid number!"),
     }
 }
 
-pub fn open_link_num(data: data::Gallery, number: i32) {
+pub fn open_link_num(data: &data::Gallery, number: i32) {
     open_in_browser(&data.image_id(number))
 }
 
-pub fn handle_missing_pics() {
-    let basedir = Path::new(KONEKODIR).parent().unwrap().join("pics");
-    if basedir.exists() {
-        return;
+/// The welcome and info images, bundled so that they are there without a network
+pub const WELCOME_PIC: &str = "71471144_p0.png";
+pub const INFO_PIC: &str = "79494300_p0.png";
+const PICS: [(&str, &[u8]); 2] = [
+    (WELCOME_PIC, include_bytes!("../pics/71471144_p0.png")),
+    (INFO_PIC, include_bytes!("../pics/79494300_p0.png")),
+];
+
+pub fn pics_dir() -> PathBuf {
+    pure::expand_home(&Path::new(KONEKODIR).parent().unwrap().join("pics"))
+}
+
+/// Writes the bundled images that are not in `basedir` yet. Existing files are
+/// never touched, as they may be the originals downloaded by an older version
+fn write_missing_pics(basedir: &Path) -> Option<()> {
+    fs::create_dir_all(basedir).ok()?;
+    for (name, bytes) in PICS.iter() {
+        let path = basedir.join(name);
+        if !path.exists() {
+            fs::write(path, bytes).ok()?;
+        }
     }
-    println!("Please wait, downloading welcome image (this will only occur once)...");
-    let baseurl = "https://raw.githubusercontent.com/twenty5151/koneko/master/pics/";
-    fs::create_dir_all(&basedir).unwrap();
-    for pic in ["71471144_p0.png", "79494300_p0.png"].iter() {
-        Command::new("curl")
-            .args(&[
-                "-s",
-                &format!("{}{}", baseurl, pic),
-                "-o",
-                &basedir.join(pic).to_str().unwrap(),
-            ])
-            .spawn();
+    Some(())
+}
+
+pub fn handle_missing_pics() {
+    if write_missing_pics(&pics_dir()).is_none() {
+        println!(
+            "Failed to write the welcome images to {}",
+            pics_dir().display()
+        );
     }
-    Command::new("clear").spawn();
 }
 
 #[cfg(test)]
 mod tests {
     use std::env;
+    use std::sync::Arc;
     use super::*;
+    use crate::system::{with_system, Recorder};
+    use image::GenericImageView;
     use rstest::*;
 
+    #[rstest]
+    fn test_open_in_browser() {
+        let recorder = Arc::new(Recorder::default());
+        with_system(recorder.clone(), || open_in_browser("76695217"));
+        assert_eq!(
+            *recorder.calls.lock().unwrap(),
+            vec!["open_url https://www.pixiv.net/artworks/76695217"]
+        );
+    }
+
+    #[rstest]
+    fn test_write_missing_pics() {
+        let dir = env::temp_dir().join("koneko-test-pics");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(WELCOME_PIC), b"kept").unwrap();
+
+        write_missing_pics(&dir).unwrap();
+        assert_eq!(fs::read(dir.join(WELCOME_PIC)).unwrap(), b"kept");
+        assert_eq!(
+            image::image_dimensions(dir.join(INFO_PIC)).unwrap(),
+            image::load_from_memory(PICS[1].1).unwrap().dimensions()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[rstest]
     fn test_cd_simple() {
         let root = env::current_dir().unwrap();